@@ -0,0 +1,234 @@
+use crate::geometry::Vec3f;
+
+const BINS: usize = 12;
+const LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECT_COST: f32 = 1.0;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Vec3f,
+    pub max: Vec3f
+}
+
+impl Aabb {
+    pub fn new(min: Vec3f, max: Vec3f) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3f::one()*f32::MAX,
+            max: Vec3f::one()*f32::MIN
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn grow(self, p: Vec3f) -> Self {
+        Self { min: self.min.min(p), max: self.max.max(p) }
+    }
+
+    pub fn centroid(&self) -> Vec3f {
+        (self.min + self.max)*0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d[0] < 0.0 {
+            return 0.0;
+        }
+        2.0*(d[0]*d[1] + d[1]*d[2] + d[2]*d[0])
+    }
+
+    /// Slab test, returns the distance at which the ray enters the box.
+    pub fn ray_intersect(&self, orig: Vec3f, inv_dir: Vec3f, t_max: f32) -> Option<f32> {
+        self.ray_span(orig, inv_dir, t_max).map(|(t0, _)| t0)
+    }
+
+    /// Distances at which the ray enters and leaves the box, clipped to `0..t_max`.
+    pub fn ray_span(&self, orig: Vec3f, inv_dir: Vec3f, t_max: f32) -> Option<(f32, f32)> {
+        let mut t0 = 0.0f32;
+        let mut t1 = t_max;
+        for a in 0..3 {
+            let mut near = (self.min[a] - orig[a])*inv_dir[a];
+            let mut far = (self.max[a] - orig[a])*inv_dir[a];
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    bounds: Aabb,
+    // Leaf: range into `indices`. Inner: `start` is the right child, the left one follows the node.
+    start: usize,
+    count: usize,
+    axis: usize
+}
+
+/// Bounding volume hierarchy over anything that can report its `Aabb`.
+/// Holds only indices, the primitives stay with the caller.
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        Self::with_leaf_size(bounds, LEAF_SIZE)
+    }
+
+    pub fn with_leaf_size(bounds: &[Aabb], leaf_size: usize) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2*bounds.len()),
+            indices: (0..bounds.len()).collect()
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len(), leaf_size.max(1));
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize, leaf_size: usize) -> usize {
+        let node = self.nodes.len();
+        let items = &mut self.indices[start..end];
+        let node_bounds = items.iter().fold(Aabb::empty(), |b, &i| b.union(bounds[i]));
+        self.nodes.push(Node { bounds: node_bounds, start, count: end - start, axis: 0 });
+        if end - start <= leaf_size {
+            return node;
+        }
+
+        let centroids = items.iter().fold(Aabb::empty(), |b, &i| b.grow(bounds[i].centroid()));
+        let extent = centroids.max - centroids.min;
+        let axis = if extent[0] > extent[1] && extent[0] > extent[2] {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0.0 {
+            return node;
+        }
+
+        let bin_of = |i: usize| {
+            let c = (bounds[i].centroid()[axis] - centroids.min[axis])/extent[axis];
+            ((c*BINS as f32) as usize).min(BINS - 1)
+        };
+        let mut bins = [(Aabb::empty(), 0usize); BINS];
+        for &i in items.iter() {
+            let b = &mut bins[bin_of(i)];
+            *b = (b.0.union(bounds[i]), b.1 + 1);
+        }
+
+        // Surface area heuristic: pick the bin boundary with the cheapest expected cost
+        let mut best = (f32::MAX, 0);
+        for split in 1..BINS {
+            let (left, nl) = bins[..split].iter()
+                .fold((Aabb::empty(), 0), |(b, n), x| (b.union(x.0), n + x.1));
+            let (right, nr) = bins[split..].iter()
+                .fold((Aabb::empty(), 0), |(b, n), x| (b.union(x.0), n + x.1));
+            if nl == 0 || nr == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + INTERSECT_COST*
+                (left.surface_area()*nl as f32 + right.surface_area()*nr as f32)
+                / node_bounds.surface_area();
+            if cost < best.0 {
+                best = (cost, split);
+            }
+        }
+        if best.1 == 0 {
+            return node;
+        }
+
+        let mut mid = 0;
+        for k in 0..items.len() {
+            if bin_of(items[k]) < best.1 {
+                items.swap(k, mid);
+                mid += 1;
+            }
+        }
+        let mid = start + mid;
+        self.build(bounds, start, mid, leaf_size);
+        let right = self.build(bounds, mid, end, leaf_size);
+        self.nodes[node].start = right;
+        self.nodes[node].count = 0;
+        self.nodes[node].axis = axis;
+        node
+    }
+
+    /// Finds the closest primitive along the ray. `hit` is called with a primitive
+    /// index and the current closest distance and reports a closer hit with its distance, if any.
+    pub fn closest<H, F>(&self, orig: Vec3f, dir: Vec3f, t_max: f32, mut hit: F) -> Option<H>
+    where F: FnMut(usize, f32) -> Option<(f32, H)> {
+        let mut best = None;
+        let mut t_max = t_max;
+        self.traverse(orig, dir, &mut t_max, |i, t| {
+            let (d, h) = hit(i, t)?;
+            best = Some(h);
+            Some(d)
+        });
+        best
+    }
+
+    /// Returns true as soon as `hit` reports an intersection closer than `t_max`.
+    pub fn any<F>(&self, orig: Vec3f, dir: Vec3f, t_max: f32, mut hit: F) -> bool
+    where F: FnMut(usize, f32) -> bool {
+        let mut t_max = t_max;
+        let mut found = false;
+        self.traverse(orig, dir, &mut t_max, |i, t| {
+            found = hit(i, t);
+            if found { Some(0.0) } else { None }
+        });
+        found
+    }
+
+    fn traverse<F>(&self, orig: Vec3f, dir: Vec3f, t_max: &mut f32, mut hit: F)
+    where F: FnMut(usize, f32) -> Option<f32> {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inv_dir = Vec3f::new(1.0/dir[0], 1.0/dir[1], 1.0/dir[2]);
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            match node.bounds.ray_intersect(orig, inv_dir, *t_max) {
+                Some(t) if t < *t_max => (),
+                _ => continue
+            }
+            if node.count > 0 {
+                for &i in &self.indices[node.start..node.start + node.count] {
+                    if let Some(d) = hit(i, *t_max) {
+                        *t_max = d;
+                        if d <= 0.0 {
+                            return;
+                        }
+                    }
+                }
+            } else {
+                let (left, right) = (n + 1, node.start);
+                // Visit the child facing the ray first, it is more likely to shrink t_max
+                if dir[node.axis] < 0.0 {
+                    stack.push(left);
+                    stack.push(right);
+                } else {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+    }
+}
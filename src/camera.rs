@@ -0,0 +1,110 @@
+use std::f32::consts::PI;
+
+use crate::geometry::Vec3f;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// Vertical field of view in radians.
+    Perspective { fov: f32 },
+    /// Height of the visible area in world units, rays are parallel.
+    Orthographic { height: f32 }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    pub position: Vec3f,
+    pub target: Vec3f,
+    pub up: Vec3f,
+    /// Rotation around the viewing direction in radians, counter-clockwise.
+    pub roll: f32,
+    pub projection: Projection
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::perspective(PI / 3.0)
+    }
+}
+
+impl Camera {
+    /// Camera at the origin looking down -Z, as in the original tinyraytracer.
+    pub fn perspective(fov: f32) -> Self {
+        Self {
+            position: Vec3f::zero(),
+            target: Vec3f::new(0.0, 0.0, -1.0),
+            up: Vec3f::new(0.0, 1.0, 0.0),
+            roll: 0.0,
+            projection: Projection::Perspective { fov }
+        }
+    }
+
+    pub fn orthographic(height: f32) -> Self {
+        Self { projection: Projection::Orthographic { height }, ..Self::default() }
+    }
+
+    pub fn look_at(self, position: Vec3f, target: Vec3f) -> Self {
+        Self { position, target, ..self }
+    }
+
+    pub fn with_up(self, up: Vec3f) -> Self {
+        Self { up, ..self }
+    }
+
+    pub fn with_roll(self, roll: f32) -> Self {
+        Self { roll, ..self }
+    }
+
+    /// Right, up and forward unit vectors of the image plane.
+    pub fn basis(&self) -> (Vec3f, Vec3f, Vec3f) {
+        let forward = (self.target - self.position).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        let (sin, cos) = self.roll.sin_cos();
+        (right*cos + up*sin, up*cos - right*sin, forward)
+    }
+
+    /// Ray through the image plane point `(x, y)`, both measured in half image
+    /// heights from the center, so `y` spans -1..1 and `x` depends on the aspect ratio.
+    pub fn ray(&self, x: f32, y: f32) -> (Vec3f, Vec3f) {
+        let (right, up, forward) = self.basis();
+        match self.projection {
+            Projection::Perspective { fov } => {
+                let scale = f32::tan(fov/2.0);
+                (self.position, (forward + right*(x*scale) + up*(y*scale)).normalize())
+            },
+            Projection::Orthographic { height } => {
+                let scale = height/2.0;
+                (self.position + right*(x*scale) + up*(y*scale), forward)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_at() {
+        let camera = Camera::perspective(PI / 2.0).look_at(Vec3f::new(0.0, 0.0, 5.0), Vec3f::new(10.0, 0.0, 5.0));
+        let (orig, dir) = camera.ray(0.0, 0.0);
+        assert!((orig - Vec3f::new(0.0, 0.0, 5.0)).norm() < 1e-6);
+        assert!((dir - Vec3f::new(1.0, 0.0, 0.0)).norm() < 1e-6);
+        // 90 degrees of fov put the top edge of the image at 45 degrees up
+        let (_, dir) = camera.ray(0.0, 1.0);
+        assert!((dir - Vec3f::new(1.0, 1.0, 0.0).normalize()).norm() < 1e-6);
+
+        let (_, dir) = camera.with_roll(PI / 2.0).ray(1.0, 0.0);
+        assert!((dir - Vec3f::new(1.0, 1.0, 0.0).normalize()).norm() < 1e-6);
+    }
+
+    #[test]
+    fn orthographic() {
+        let camera = Camera::orthographic(4.0);
+        let (o1, d1) = camera.ray(-1.0, 1.0);
+        let (o2, d2) = camera.ray(1.0, -1.0);
+        assert!((d1 - d2).norm() < 1e-6);
+        assert!((o1 - Vec3f::new(-2.0, 2.0, 0.0)).norm() < 1e-6);
+        assert!((o2 - Vec3f::new(2.0, -2.0, 0.0)).norm() < 1e-6);
+    }
+}
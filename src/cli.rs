@@ -0,0 +1,373 @@
+use std::io::Error;
+use std::str::FromStr;
+
+use crate::camera::Projection;
+use crate::march::{Kaboom, Shading, Tracer, Volume};
+use crate::render::{ImageFormat, RenderType, View};
+use crate::sampling::{Filter, Pattern};
+use crate::scenefile::SceneFile;
+
+pub const USAGE: &str = "\
+Usage: ray-rs [OPTIONS]
+
+Renders the tinyraytracer scene, a path traced version of it or the tinykaboom
+explosion to an image file.
+
+Options:
+  -m, --mode <MODE>      What to render: raytrace, pathtrace or kaboom
+                         [default: raytrace]
+  -s, --scene <FILE>     Scene description to render [default: built-in scene]
+      --width <PX>       Image width, overrides the scene camera
+      --height <PX>      Image height, overrides the scene camera
+      --fov <DEGREES>    Vertical field of view, overrides the scene camera
+  -o, --output <FILE>    Output path [default: scene.ppm or kaboom.ppm]
+  -f, --format <FORMAT>  Output image format: ppm or png [default: from extension]
+  -d, --depth <N>        Recursion depth, or bounces before Russian roulette
+                         when path tracing
+  -n, --samples <N>      Samples per pixel
+      --shadow-samples <N>
+                         Shadow rays per area light and shading point
+      --pattern <NAME>   Sample placement: regular, jittered, halton or sobol
+      --filter <NAME>    Reconstruction filter: box, tent, gaussian or mitchell
+      --time <T>         Moment of the kaboom explosion, 0 is the ignition
+                         [default: 1]
+      --radius <R>       Kaboom fireball radius [default: 1.5]
+      --amplitude <A>    Kaboom noise displacement [default: 1]
+      --steps <N>        Kaboom sphere tracing steps per ray [default: 1024]
+      --epsilon <E>      Distance to the fireball surface that counts as a hit
+                         [default: 0.0001]
+      --relaxation <W>   Over-relaxation of kaboom steps, 1 to 2 [default: 1.6]
+      --penumbra <K>     Sharpness of kaboom shadow edges, 0 casts no shadows
+                         [default: 0]
+      --occlusion <N>    Kaboom ambient occlusion samples, 0 for none
+                         [default: 0]
+      --volume           Render the kaboom fireball as a glowing medium
+      --step-size <S>    Distance between volume samples, implies --volume
+                         [default: 0.02]
+      --extinction <E>   Absorption of the volume at full density, implies
+                         --volume [default: 10]
+      --frames <N>       Write N kaboom frames from time 0 to --time, or the
+                         scene file's kaboom time, numbered like kaboom_0000.ppm
+  -j, --threads <N>      Worker threads [default: one per core]
+  -h, --help             Print this help
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    RayTrace,
+    PathTrace,
+    Kaboom
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub mode: Mode,
+    pub scene: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub fov: Option<f32>,
+    pub output: Option<String>,
+    pub format: Option<ImageFormat>,
+    pub depth: Option<usize>,
+    pub samples: Option<usize>,
+    pub shadow_samples: Option<usize>,
+    pub pattern: Option<Pattern>,
+    pub filter: Option<Filter>,
+    pub time: Option<f32>,
+    pub frames: Option<usize>,
+    pub radius: Option<f32>,
+    pub amplitude: Option<f32>,
+    pub steps: Option<usize>,
+    pub epsilon: Option<f32>,
+    pub relaxation: Option<f32>,
+    pub penumbra: Option<f32>,
+    pub occlusion: Option<usize>,
+    pub volume: bool,
+    pub step_size: Option<f32>,
+    pub extinction: Option<f32>,
+    pub threads: Option<usize>
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            mode: Mode::RayTrace,
+            scene: None,
+            width: None,
+            height: None,
+            fov: None,
+            output: None,
+            format: None,
+            depth: None,
+            samples: None,
+            shadow_samples: None,
+            pattern: None,
+            filter: None,
+            time: None,
+            frames: None,
+            radius: None,
+            amplitude: None,
+            steps: None,
+            epsilon: None,
+            relaxation: None,
+            penumbra: None,
+            occlusion: None,
+            volume: false,
+            step_size: None,
+            extinction: None,
+            threads: None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Render(Box<Options>)
+}
+
+fn positive<T: FromStr + PartialOrd + Default>(name: &str, value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(v) if v > T::default() => Ok(v),
+        _ => Err(format!("invalid value '{}' for {}: expected a positive number", value, name))
+    }
+}
+
+/// Parses the arguments that follow the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut opts = Options::default();
+    let mut kaboom_only = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        // Both `--name value` and `--name=value` are accepted
+        let (name, inline) = match arg.split_once('=') {
+            Some((n, v)) if n.starts_with("--") => (n.to_string(), Some(v.to_string())),
+            _ => (arg, None)
+        };
+        if ["--time", "--frames", "--radius", "--amplitude", "--steps", "--epsilon", "--relaxation", "--penumbra", "--occlusion", "--volume", "--step-size", "--extinction"]
+            .contains(&name.as_str()) {
+            kaboom_only.get_or_insert(name.clone());
+        }
+        let mut value = || inline.clone().or_else(|| args.next())
+            .ok_or_else(|| format!("{} requires a value", name));
+        match name.as_str() {
+            "-m" | "--mode" => opts.mode = match value()?.as_str() {
+                "raytrace" => Mode::RayTrace,
+                "pathtrace" => Mode::PathTrace,
+                "kaboom" => Mode::Kaboom,
+                v => return Err(format!("unknown mode '{}': expected raytrace, pathtrace or kaboom", v))
+            },
+            "-s" | "--scene" => opts.scene = Some(value()?),
+            "--width" => opts.width = Some(positive(&name, &value()?)?),
+            "--height" => opts.height = Some(positive(&name, &value()?)?),
+            "--fov" => {
+                let fov: f32 = positive(&name, &value()?)?;
+                if fov >= 180.0 {
+                    return Err(format!("invalid value '{}' for --fov: must be below 180 degrees", fov));
+                }
+                opts.fov = Some(fov);
+            },
+            "-o" | "--output" => opts.output = Some(value()?),
+            "-f" | "--format" => opts.format = Some(value()?.parse()?),
+            "-d" | "--depth" => {
+                let v = value()?;
+                opts.depth = Some(v.parse().map_err(|_| format!("invalid value '{}' for {}: expected a number", v, name))?);
+            },
+            "-n" | "--samples" => opts.samples = Some(positive(&name, &value()?)?),
+            "--shadow-samples" => opts.shadow_samples = Some(positive(&name, &value()?)?),
+            "--pattern" => opts.pattern = Some(value()?.parse()?),
+            "--filter" => opts.filter = Some(value()?.parse()?),
+            "--time" => {
+                let v = value()?;
+                opts.time = Some(v.parse().ok().filter(|t: &f32| *t >= 0.0)
+                    .ok_or_else(|| format!("invalid value '{}' for {}: expected a time of at least 0", v, name))?);
+            },
+            "--frames" => opts.frames = Some(positive(&name, &value()?)?),
+            "--radius" => opts.radius = Some(positive(&name, &value()?)?),
+            "--amplitude" => {
+                let v = value()?;
+                opts.amplitude = Some(v.parse().ok().filter(|a: &f32| *a >= 0.0)
+                    .ok_or_else(|| format!("invalid value '{}' for {}: expected a number of at least 0", v, name))?);
+            },
+            "--steps" => opts.steps = Some(positive(&name, &value()?)?),
+            "--epsilon" => opts.epsilon = Some(positive(&name, &value()?)?),
+            "--relaxation" => {
+                let v = value()?;
+                opts.relaxation = Some(v.parse().ok().filter(|w: &f32| (1.0..=2.0).contains(w))
+                    .ok_or_else(|| format!("invalid value '{}' for {}: expected a number from 1 to 2", v, name))?);
+            },
+            "--penumbra" => {
+                let v = value()?;
+                opts.penumbra = Some(v.parse().ok().filter(|k: &f32| *k >= 0.0)
+                    .ok_or_else(|| format!("invalid value '{}' for {}: expected a number of at least 0", v, name))?);
+            },
+            "--occlusion" => {
+                let v = value()?;
+                opts.occlusion = Some(v.parse().map_err(|_| format!("invalid value '{}' for {}: expected a number", v, name))?);
+            },
+            "--volume" => opts.volume = true,
+            "--step-size" => {
+                opts.step_size = Some(positive(&name, &value()?)?);
+                opts.volume = true;
+            },
+            "--extinction" => {
+                opts.extinction = Some(positive(&name, &value()?)?);
+                opts.volume = true;
+            },
+            "-j" | "--threads" => opts.threads = Some(positive(&name, &value()?)?),
+            _ => return Err(format!("unknown option '{}'", name))
+        }
+    }
+    match kaboom_only {
+        Some(name) if opts.mode != Mode::Kaboom => Err(format!("{} only applies to kaboom mode", name)),
+        _ => Ok(Command::Render(Box::new(opts)))
+    }
+}
+
+/// Path of frame `index` of an animation, numbered before the extension.
+pub fn frame_path(output: &str, index: usize) -> String {
+    let (stem, ext) = match output.rfind('.') {
+        Some(dot) if !output[dot..].contains(['/', '\\']) => output.split_at(dot),
+        _ => (output, "")
+    };
+    format!("{}_{:04}{}", stem, index, ext)
+}
+
+pub fn run(opts: &Options) -> Result<(), Error> {
+    if let Some(n) = opts.threads {
+        rayon::ThreadPoolBuilder::new().num_threads(n).build_global()
+            .map_err(Error::other)?;
+    }
+    let file = match &opts.scene {
+        Some(p) => SceneFile::load(p)?,
+        None => SceneFile::parse(crate::DEFAULT_SCENE)?
+    };
+    let (width, height) = match (opts.mode, &opts.scene) {
+        (Mode::Kaboom, None) => (1024, 768),
+        _ => (file.width, file.height)
+    };
+    let mut camera = file.camera;
+    if let Some(fov) = opts.fov {
+        camera.projection = Projection::Perspective { fov: fov.to_radians() };
+    }
+    let view = View::from_camera(opts.width.unwrap_or(width), opts.height.unwrap_or(height), camera)
+        .with_depth(opts.depth.unwrap_or(file.depth))
+        .with_samples(opts.samples.unwrap_or(file.samples))
+        .with_shadow_samples(opts.shadow_samples.unwrap_or(file.shadow_samples))
+        .with_pattern(opts.pattern.unwrap_or(file.pattern))
+        .with_filter(opts.filter.unwrap_or(file.filter));
+
+    let output = opts.output.clone().unwrap_or_else(|| match opts.mode {
+        Mode::RayTrace | Mode::PathTrace => "scene.ppm".to_string(),
+        Mode::Kaboom => "kaboom.ppm".to_string()
+    });
+    let format = opts.format.unwrap_or_else(|| ImageFormat::from_path(&output));
+    let kaboom = Kaboom {
+        radius: opts.radius.unwrap_or(file.kaboom.radius),
+        amplitude: opts.amplitude.unwrap_or(file.kaboom.amplitude),
+        tracer: Tracer {
+            max_steps: opts.steps.unwrap_or(file.kaboom.tracer.max_steps),
+            epsilon: opts.epsilon.unwrap_or(file.kaboom.tracer.epsilon),
+            relaxation: opts.relaxation.unwrap_or(file.kaboom.tracer.relaxation),
+            ..file.kaboom.tracer
+        },
+        shading: Shading {
+            penumbra: opts.penumbra.unwrap_or(file.kaboom.shading.penumbra),
+            occlusion: opts.occlusion.unwrap_or(file.kaboom.shading.occlusion),
+            ..file.kaboom.shading.clone()
+        },
+        time: opts.time.unwrap_or(file.kaboom.time),
+        volume: match (opts.volume, file.kaboom.volume) {
+            (false, v) => v,
+            (true, v) => {
+                let v = v.unwrap_or_default();
+                Some(Volume {
+                    step: opts.step_size.unwrap_or(v.step),
+                    extinction: opts.extinction.unwrap_or(v.extinction)
+                })
+            }
+        },
+        ..file.kaboom.clone()
+    };
+    if let Some(frames) = opts.frames {
+        let end = kaboom.time;
+        for i in 0..frames {
+            let time = if frames > 1 { end*i as f32/(frames - 1) as f32 } else { end };
+            view.render(RenderType::Kaboom(kaboom.clone().with_time(time))).save_as(&frame_path(&output, i), format)?;
+        }
+        return Ok(());
+    }
+
+    let frame = match opts.mode {
+        Mode::RayTrace => view.render(RenderType::RayTrace(&file.scene()?)),
+        Mode::PathTrace => view.render(RenderType::PathTrace(&file.scene()?)),
+        Mode::Kaboom => view.render(RenderType::Kaboom(kaboom))
+    };
+    frame.save_as(&output, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn options() {
+        let cmd = parse(&["-m", "kaboom", "--width=320", "--height", "240", "-n", "4", "-o", "out.ppm"]).unwrap();
+        assert_eq!(cmd, Command::Render(Box::new(Options {
+            mode: Mode::Kaboom,
+            width: Some(320),
+            height: Some(240),
+            samples: Some(4),
+            output: Some("out.ppm".to_string()),
+            ..Options::default()
+        })));
+        assert_eq!(parse(&["--depth", "2", "--help"]).unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn animation() {
+        let cmd = parse(&["-m", "kaboom", "--frames", "48", "--time=1.5"]).unwrap();
+        assert_eq!(cmd, Command::Render(Box::new(Options {
+            mode: Mode::Kaboom,
+            time: Some(1.5),
+            frames: Some(48),
+            ..Options::default()
+        })));
+        assert!(parse(&["-m", "kaboom", "--time", "-1"]).is_err());
+        assert!(parse(&["--frames", "10"]).is_err());
+        assert_eq!(parse(&["--steps", "10"]), Err("--steps only applies to kaboom mode".to_string()));
+        assert!(parse(&["-m", "kaboom", "--steps", "10", "--relaxation", "1.2", "-s", "boom.scene"]).is_ok());
+        assert!(parse(&["-m", "kaboom", "--relaxation", "2.5"]).is_err());
+        assert!(parse(&["-m", "kaboom", "--penumbra", "0", "--occlusion", "0"]).is_ok());
+        assert!(parse(&["-m", "kaboom", "--penumbra", "-1"]).is_err());
+        assert!(parse(&["-m", "kaboom", "--amplitude", "-1"]).is_err());
+        assert_eq!(parse(&["--occlusion", "3"]), Err("--occlusion only applies to kaboom mode".to_string()));
+        let cmd = parse(&["-m", "kaboom", "--extinction", "8"]).unwrap();
+        assert_eq!(cmd, Command::Render(Box::new(Options {
+            mode: Mode::Kaboom,
+            volume: true,
+            extinction: Some(8.0),
+            ..Options::default()
+        })));
+        assert!(parse(&["--volume"]).is_err());
+        assert_eq!(frame_path("out/kaboom.png", 7), "out/kaboom_0007.png");
+        assert_eq!(frame_path("out.d/kaboom", 12), "out.d/kaboom_0012");
+    }
+
+    #[test]
+    fn bad_input() {
+        assert!(parse(&["--width", "0"]).is_err());
+        assert!(parse(&["--fov", "200"]).is_err());
+        assert!(parse(&["--mode", "raymarch"]).is_err());
+        assert!(parse(&["--samples"]).is_err());
+        assert!(parse(&["--filter", "lanczos"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+    }
+}
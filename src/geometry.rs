@@ -5,7 +5,7 @@ use std::ops::{Add, Mul, Neg, Sub};
 
 use num_traits::{Float, Num};
 
-#[derive(Debug,Copy,Clone)]
+#[derive(Debug,Copy,Clone,PartialEq)]
 pub struct GVec<T, const D: usize> ([T; D]);
 
 impl<T, const D: usize> From<[T; D]> for GVec<T, D>{
@@ -41,6 +41,33 @@ impl<T: Float, const D: usize> GVec<T, D> {
     pub fn normalize(self) -> Self {
         self * (T::one() / self.norm())
     }
+
+    pub fn min(self, rhs: Self) -> Self {
+        let mut arr = self.0;
+        for (i, a) in arr.iter_mut().zip(rhs.0) {
+            *i = i.min(a);
+        }
+        Self(arr)
+    }
+
+    pub fn max(self, rhs: Self) -> Self {
+        let mut arr = self.0;
+        for (i, a) in arr.iter_mut().zip(rhs.0) {
+            *i = i.max(a);
+        }
+        Self(arr)
+    }
+}
+
+impl<T: Num+Copy, const D: usize> GVec<T, D> {
+    /// Component-wise product, e.g. to filter a color by another one.
+    pub fn hadamard(self, rhs: Self) -> Self {
+        let mut arr = self.0;
+        for (i, a) in arr.iter_mut().zip(rhs.0) {
+            *i = *i * a;
+        }
+        Self(arr)
+    }
 }
 
 impl<T: Num+Copy, const D: usize> Mul<T> for GVec<T, D> {
@@ -56,7 +83,7 @@ impl<T: Num, const D: usize> Mul for GVec<T, D> {
 
     fn mul(self, rhs: Self) -> Self::Output {
         self.0.into_iter()
-        .zip(rhs.0.into_iter())
+        .zip(rhs.0)
         .fold(T::zero(), |a, x| a + x.0*x.1)
     }
 }
@@ -66,7 +93,7 @@ impl<T:Num+Copy, const D: usize> Add for GVec<T, D> {
 
     fn add(self, rhs: Self) -> Self::Output {
         let mut arr = self.0;
-        for (i, a) in arr.iter_mut().zip(rhs.0.into_iter()) {
+        for (i, a) in arr.iter_mut().zip(rhs.0) {
             *i = *i + a;
         }
         Self(
@@ -80,7 +107,7 @@ impl<T:Num+Copy, const D: usize> Sub for GVec<T, D> {
 
     fn sub(self, rhs: Self) -> Self::Output {
         let mut arr = self.0;
-        for (i, a) in arr.iter_mut().zip(rhs.0.into_iter()) {
+        for (i, a) in arr.iter_mut().zip(rhs.0) {
             *i = *i - a;
         }
         Self(
@@ -109,6 +136,23 @@ impl Vec3f {
     pub fn one() -> Self {
         Self::from([1.0, 1.0, 1.0])
     }
+    pub fn cross(self, rhs: Self) -> Self {
+        Self::new(
+            self[1]*rhs[2] - self[2]*rhs[1],
+            self[2]*rhs[0] - self[0]*rhs[2],
+            self[0]*rhs[1] - self[1]*rhs[0]
+        )
+    }
+    /// Mirror image of this direction about the plane with normal `n`.
+    pub fn reflect(self, n: Self) -> Self {
+        self - n*2.0*(self*n)
+    }
+    /// Any two unit vectors that complete this unit vector to an orthonormal basis.
+    pub fn tangents(self) -> (Self, Self) {
+        let a = if self[0].abs() > 0.9 { Self::new(0.0, 1.0, 0.0) } else { Self::new(1.0, 0.0, 0.0) };
+        let t = self.cross(a).normalize();
+        (t, self.cross(t))
+    }
 }
 
 pub type Vec2f = GVec<f32,2>;
@@ -156,7 +200,7 @@ mod tests {
     #[should_panic]
     fn out_of_bound() {
         let v = GVec::from([0.0, 1.0, -1.0]);
-        v[4];
+        let _ = v[4];
     }
 
     #[test]
@@ -170,7 +214,7 @@ mod tests {
     fn norm3() {
         let v = GVec::from([0.0, 5.0, -5.0]);
         println!("{}", v.norm());
-        assert_eq!((v.norm() - 50.0.sqrt()).abs() < 1e-5, true)
+        assert!((v.norm() - 50.0.sqrt()).abs() < 1e-5)
     }
 
     #[test]
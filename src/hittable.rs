@@ -0,0 +1,19 @@
+use crate::bvh::Aabb;
+use crate::geometry::{Vec2f, Vec3f};
+use crate::material::Material;
+
+pub struct Hit<'a> {
+    pub dist: f32,
+    pub point: Vec3f,
+    pub normal: Vec3f,
+    pub material: &'a Material,
+    pub uv: Vec2f
+}
+
+/// Anything a `Scene` can hold. Objects are shared between render threads, hence `Sync`.
+pub trait Hittable: Sync {
+    /// Closest intersection in front of `orig` that is nearer than `t_max`.
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>>;
+
+    fn bounds(&self) -> Aabb;
+}
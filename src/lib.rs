@@ -1,59 +1,37 @@
 use std::f32::consts::PI;
 use std::io::Error;
 
-use render::{View, Scene, RenderType};
+use render::{View, RenderType};
 
-use crate::geometry::{Vec3f, Vec4f};
-use crate::render::{Light};
-use crate::sphere::{Sphere, Material};
+use crate::scenefile::SceneFile;
 
 pub mod geometry;
+pub mod bvh;
+pub mod hittable;
+pub mod material;
+pub mod texture;
+pub mod camera;
+pub mod light;
 pub mod render;
+pub mod sampling;
+pub mod png;
+pub mod ppm;
 pub mod sphere;
+pub mod mesh;
+pub mod plane;
+pub mod scenefile;
+pub mod cli;
 pub mod march;
+pub mod sdf;
 pub mod noise;
 
-pub fn run() -> Result<(), Error> {
-    let ivory = Material {
-        diffuse_color: Vec3f::new(0.4, 0.4, 0.3),
-        albedo: Vec4f::new(0.6, 0.3, 0.1, 0.0),
-        specular_exp: 50.,
-        refractive_index: 1.0
-    };
-    let red_rubber = Material {
-        diffuse_color: Vec3f::new(0.3, 0.1, 0.1),
-        albedo: Vec4f::new(0.9,  0.1, 0.0, 0.0),
-        specular_exp: 10.,
-        refractive_index: 1.0
-    };
-    let mirror = Material {
-        diffuse_color: Vec3f::one(),
-        albedo: Vec4f::new(0.2, 10.0, 0.8, 0.0),
-        specular_exp: 1425.0,
-        refractive_index: 1.0
-    };
-    let glass = Material {
-        albedo: Vec4f::new(0.0,  0.5, 0.1, 0.8),
-        diffuse_color: Vec3f::new(0.6, 0.7, 0.8),
-        refractive_index: 2.5,
-        specular_exp: 125.0
-    };
-
-    let spheres = vec![
-        Sphere::new(Vec3f::new(-3.0, 0.0, -16.0), 2.0, &ivory),
-        Sphere::new(Vec3f::new(-1.0, -1.5, -12.0), 2.0, &glass),
-        Sphere::new(Vec3f::new(1.5, -0.5, -18.0), 3.0, &red_rubber),
-        Sphere::new(Vec3f::new(7.0, 5.0, -18.0), 4.0, &mirror)
-    ];
+/// The classic tinyraytracer scene, shared by the binary and the benchmarks.
+pub const DEFAULT_SCENE: &str = include_str!("../scenes/default.scene");
 
-    let lights = vec![
-        Light::new(Vec3f::new(-20.0, 20.0,  20.0), 1.5),
-        Light::new(Vec3f::new( 30.0, 50.0, -25.0), 1.8),
-        Light::new(Vec3f::new( 30.0, 20.0,  30.0), 1.7),
-    ];
-    let scene = Scene::new(spheres, lights);
+pub fn run() -> Result<(), Error> {
+    let file = SceneFile::parse(DEFAULT_SCENE)?;
+    let scene = file.scene()?;
     let small = View::new(1024,768,PI / 3.0);
-    let fs = View::new(2560,1920,PI / 3.0);
-    fs.render(RenderType::RayTrace(&scene)).save("./scene.ppm")?;
-    small.render(RenderType::Kaboom).save("./kaboom.ppm")
+    file.view().render(RenderType::RayTrace(&scene)).save("./scene.ppm")?;
+    small.render(RenderType::Kaboom(march::Kaboom::default())).save("./kaboom.ppm")
 }
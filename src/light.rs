@@ -0,0 +1,251 @@
+use std::f32::consts::PI;
+
+use crate::geometry::Vec3f;
+use crate::sampling::Rng;
+
+/// Extent of a light source. Area lights shine equally in all directions from
+/// every point of their surface and cast soft shadows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightShape {
+    Point,
+    Sphere { radius: f32 },
+    /// Parallelogram centered on the light position.
+    Rect { edge_u: Vec3f, edge_v: Vec3f },
+    Disk { normal: Vec3f, radius: f32 }
+}
+
+/// Which way a light shines.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    /// Equally in all directions from its position.
+    Omni,
+    /// Parallel rays travelling along `direction` from infinitely far away,
+    /// the position, shape and falloff are ignored.
+    Directional { direction: Vec3f },
+    /// Cone around `direction`, full strength within `inner` and fading out
+    /// towards `outer`, both half-angles in radians.
+    Spot { direction: Vec3f, inner: f32, outer: f32 }
+}
+
+/// How a light weakens with distance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Falloff {
+    /// Same strength at any distance, as in tinyraytracer.
+    None,
+    InverseSquare,
+    /// `1/(constant + linear*d + quadratic*d^2)`.
+    Custom { constant: f32, linear: f32, quadratic: f32 }
+}
+
+impl Falloff {
+    pub fn attenuation(&self, dist: f32) -> f32 {
+        match *self {
+            Falloff::None => 1.0,
+            Falloff::InverseSquare => 1.0/(dist*dist).max(1e-8),
+            Falloff::Custom { constant, linear, quadratic } =>
+                1.0/(constant + linear*dist + quadratic*dist*dist).max(1e-8)
+        }
+    }
+}
+
+/// Light arriving at a shading point from one sample of a light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Incident {
+    /// Unit vector from the shading point towards the light.
+    pub dir: Vec3f,
+    /// Distance to the light, infinite for directional lights.
+    pub dist: f32,
+    pub radiance: Vec3f
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    pub pos: Vec3f,
+    pub color: Vec3f,
+    pub intensity: f32,
+    pub shape: LightShape,
+    pub kind: LightKind,
+    pub falloff: Falloff
+}
+
+impl Light {
+    /// White point light that does not weaken with distance.
+    pub fn new(pos: Vec3f, intensity: f32) -> Self {
+        Self {
+            pos,
+            color: Vec3f::one(),
+            intensity,
+            shape: LightShape::Point,
+            kind: LightKind::Omni,
+            falloff: Falloff::None
+        }
+    }
+
+    pub fn sphere(pos: Vec3f, radius: f32, intensity: f32) -> Self {
+        Self { shape: LightShape::Sphere { radius }, ..Self::new(pos, intensity) }
+    }
+
+    pub fn rect(pos: Vec3f, edge_u: Vec3f, edge_v: Vec3f, intensity: f32) -> Self {
+        Self { shape: LightShape::Rect { edge_u, edge_v }, ..Self::new(pos, intensity) }
+    }
+
+    /// Panics if the normal is zero.
+    pub fn disk(pos: Vec3f, normal: Vec3f, radius: f32, intensity: f32) -> Self {
+        assert!(normal.norm() > 0.0, "disk light normal must be non-zero");
+        Self { shape: LightShape::Disk { normal: normal.normalize(), radius }, ..Self::new(pos, intensity) }
+    }
+
+    /// Sun shining along `direction`. Panics if the direction is zero.
+    pub fn directional(direction: Vec3f, intensity: f32) -> Self {
+        assert!(direction.norm() > 0.0, "light direction must be non-zero");
+        Self { kind: LightKind::Directional { direction: direction.normalize() }, ..Self::new(Vec3f::zero(), intensity) }
+    }
+
+    /// Panics unless `0 <= inner < outer <= PI/2`, or if the direction is zero.
+    pub fn spot(pos: Vec3f, direction: Vec3f, inner: f32, outer: f32, intensity: f32) -> Self {
+        assert!(direction.norm() > 0.0, "light direction must be non-zero");
+        assert!(0.0 <= inner && inner < outer && outer <= PI/2.0, "spot angles must satisfy 0 <= inner < outer <= 90 degrees");
+        Self { kind: LightKind::Spot { direction: direction.normalize(), inner, outer }, ..Self::new(pos, intensity) }
+    }
+
+    pub fn with_color(self, color: Vec3f) -> Self {
+        Self { color, ..self }
+    }
+
+    pub fn with_falloff(self, falloff: Falloff) -> Self {
+        Self { falloff, ..self }
+    }
+
+    /// Light reaching `point` from one random point on the light, not accounting for shadows.
+    pub fn incident(&self, point: Vec3f, rng: &mut Rng) -> Incident {
+        let radiance = self.color*self.intensity;
+        if let LightKind::Directional { direction } = self.kind {
+            return Incident { dir: -direction, dist: f32::INFINITY, radiance };
+        }
+        let to_light = self.sample(point, rng) - point;
+        let dist = to_light.norm();
+        let dir = to_light*(1.0/dist);
+        let cone = match self.kind {
+            LightKind::Spot { direction, inner, outer } => {
+                let cos = -(dir*direction);
+                let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+                if cos >= cos_inner {
+                    1.0
+                } else if cos <= cos_outer {
+                    0.0
+                } else {
+                    let t = (cos - cos_outer)/(cos_inner - cos_outer);
+                    t*t*(3.0 - 2.0*t)
+                }
+            },
+            _ => 1.0
+        };
+        Incident { dir, dist, radiance: radiance*(cone*self.falloff.attenuation(dist)) }
+    }
+
+    /// Shadow rays needed for this light when area lights get `n`, a point light casts hard shadows with one.
+    pub fn shadow_samples(&self, n: usize) -> usize {
+        match (self.kind, self.shape) {
+            (LightKind::Directional { .. }, _) | (_, LightShape::Point) => 1,
+            _ => n.max(1)
+        }
+    }
+
+    /// Random point on the light as seen from `from`, uniformly distributed over
+    /// the surface, or over the silhouette for a sphere.
+    pub fn sample(&self, from: Vec3f, rng: &mut Rng) -> Vec3f {
+        match self.shape {
+            LightShape::Point => self.pos,
+            LightShape::Sphere { radius } => {
+                // Any silhouette will do when sampling from the center
+                let d = from - self.pos;
+                let w = if d.norm() > 0.0 { d.normalize() } else { Vec3f::new(0.0, 0.0, 1.0) };
+                let (t, b) = w.tangents();
+                let (x, y) = unit_disk(rng);
+                let h = (1.0 - x*x - y*y).max(0.0).sqrt();
+                self.pos + (t*x + b*y + w*h)*radius
+            },
+            LightShape::Rect { edge_u, edge_v } =>
+                self.pos + edge_u*(rng.next_f32() - 0.5) + edge_v*(rng.next_f32() - 0.5),
+            LightShape::Disk { normal, radius } => {
+                let (t, b) = normal.tangents();
+                let (x, y) = unit_disk(rng);
+                self.pos + (t*x + b*y)*radius
+            }
+        }
+    }
+}
+
+fn unit_disk(rng: &mut Rng) -> (f32, f32) {
+    let r = rng.next_f32().sqrt();
+    let phi = 2.0*PI*rng.next_f32();
+    (r*phi.cos(), r*phi.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_lie_on_light() {
+        let mut rng = Rng::new(1);
+        let from = Vec3f::new(0.0, 0.0, 10.0);
+        let sphere = Light::sphere(Vec3f::zero(), 2.0, 1.0);
+        let disk = Light::disk(Vec3f::zero(), Vec3f::new(0.0, 1.0, 0.0), 2.0, 1.0);
+        let rect = Light::rect(Vec3f::zero(), Vec3f::new(2.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 4.0), 1.0);
+        for _ in 0..100 {
+            let p = sphere.sample(from, &mut rng);
+            assert!((p.norm() - 2.0).abs() < 1e-4 && p[2] >= 0.0);
+            let p = disk.sample(from, &mut rng);
+            assert!(p.norm() <= 2.0 + 1e-4 && p[1] == 0.0);
+            let p = rect.sample(from, &mut rng);
+            assert!(p[0].abs() <= 1.0 && p[1] == 0.0 && p[2].abs() <= 2.0);
+            let p = sphere.sample(Vec3f::zero(), &mut rng);
+            assert!((p.norm() - 2.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn spot_cone_and_falloff() {
+        let mut rng = Rng::new(1);
+        let spot = Light::spot(Vec3f::zero(), Vec3f::new(0.0, -1.0, 0.0), 0.2, 0.4, 2.0)
+            .with_color(Vec3f::new(1.0, 0.5, 0.0))
+            .with_falloff(Falloff::InverseSquare);
+        let inside = spot.incident(Vec3f::new(0.0, -2.0, 0.0), &mut rng);
+        assert!((inside.radiance - Vec3f::new(0.5, 0.25, 0.0)).norm() < 1e-6);
+        assert!((inside.dir - Vec3f::new(0.0, 1.0, 0.0)).norm() < 1e-6);
+        let edge = spot.incident(Vec3f::new(0.3f32.tan()*2.0, -2.0, 0.0), &mut rng);
+        assert!(edge.radiance[0] > 0.0 && edge.radiance[0] < 0.5);
+        let outside = spot.incident(Vec3f::new(2.0, -2.0, 0.0), &mut rng);
+        assert_eq!(outside.radiance, Vec3f::zero());
+
+        let sun = Light::directional(Vec3f::new(0.0, -2.0, 0.0), 1.0);
+        let from_sun = sun.incident(Vec3f::new(5.0, 0.0, 5.0), &mut rng);
+        assert_eq!(from_sun.dist, f32::INFINITY);
+        assert!((from_sun.dir - Vec3f::new(0.0, 1.0, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn spot_inner_past_outer() {
+        Light::spot(Vec3f::zero(), Vec3f::new(0.0, -1.0, 0.0), 0.4, 0.2, 1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_sun_direction() {
+        Light::directional(Vec3f::zero(), 1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_spot_direction() {
+        Light::spot(Vec3f::zero(), Vec3f::zero(), 0.2, 0.4, 1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_disk_normal() {
+        Light::disk(Vec3f::zero(), Vec3f::zero(), 1.0, 1.0);
+    }
+}
@@ -1,5 +1,19 @@
-use std::io::Error;
+use std::process::exit;
 
-fn main() -> Result<(), Error> {
-    ray_rs::run()
+use ray_rs::cli::{self, Command};
+
+fn main() {
+    match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Help) => print!("{}", cli::USAGE),
+        Ok(Command::Render(opts)) => {
+            if let Err(e) = cli::run(&opts) {
+                eprintln!("error: {}", e);
+                exit(1);
+            }
+        },
+        Err(e) => {
+            eprintln!("error: {}\nTry 'ray-rs --help' for more information.", e);
+            exit(2);
+        }
+    }
 }
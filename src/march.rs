@@ -1,48 +1,446 @@
-use crate::{geometry::Vec3f, noise::{fractal_brownian_motion, lerp}};
+use std::fmt;
+use std::str::FromStr;
 
-const SPHERE_RADIUS: f32 = 1.5;
-const NOISE_AMP: f32 = 1.0;
-const STEPS: usize = 128;
+use crate::{geometry::Vec3f, light::Light, noise::{lerp, Basis, Noise}, sampling::Rng, sdf::Sdf};
 
-fn signed_dist(p: Vec3f) -> f32 {
-    let displacement = -fractal_brownian_motion(p*3.4) * NOISE_AMP;
-    p.norm() - (SPHERE_RADIUS + displacement)
+// How far the noise drifts upwards per unit of time
+const SCROLL: f32 = 0.6;
+// Shadow rays start this far off the surface, beyond the error of marched hits and normals
+const SHADOW_BIAS: f32 = 1e-2;
+
+/// Time of the classic tinykaboom still. Time 0 is the ignition, the fireball
+/// grows quickly at first and keeps swelling slowly past 1.
+pub const STILL: f32 = 1.0;
+
+/// Look of the tinykaboom fireball and how it is ray marched. The default is
+/// the classic still.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kaboom {
+    /// Radius of the fully grown sphere that the noise displaces.
+    pub radius: f32,
+    /// Depth of the noise displacement, not negative.
+    pub amplitude: f32,
+    /// Noise that pulls the surface in, in 0..1 through `Noise::unit`.
+    pub noise: Noise,
+    pub tracer: Tracer,
+    /// Where the fireball sits, in front of the default camera.
+    pub center: Vec3f,
+    /// How the opaque fireball is lit, the volume glows by itself.
+    pub shading: Shading,
+    pub background: Vec3f,
+    /// See `STILL`.
+    pub time: f32,
+    /// Render the fireball as a glowing, translucent medium instead of an opaque surface.
+    pub volume: Option<Volume>
+}
+
+/// How the fireball is integrated as a participating medium. The density
+/// rises from 0 at the displaced surface to 1 one `amplitude` deep, and every
+/// bit of it glows in the palette color of its depth.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Volume {
+    /// Distance between density samples along the ray.
+    pub step: f32,
+    /// Light absorbed per unit of distance at full density.
+    pub extinction: f32
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self { step: 0.02, extinction: 10.0 }
+    }
+}
+
+impl Default for Kaboom {
+    fn default() -> Self {
+        Self {
+            radius: 1.5,
+            amplitude: 1.0,
+            noise: Noise::new(0).with_basis(Basis::Classic).with_frequency(3.4),
+            // The classic noise is steep, so its Lipschitz steps are short
+            tracer: Tracer { max_steps: 1024, ..Tracer::default() },
+            center: Vec3f::new(0.0, 0.0, -3.0),
+            shading: Shading::default(),
+            background: Vec3f::new(0.2, 0.7, 0.8),
+            time: STILL,
+            volume: None
+        }
+    }
+}
+
+impl Kaboom {
+    pub fn with_time(self, time: f32) -> Self {
+        Self { time, ..self }
+    }
+
+    pub fn with_volume(self, volume: Volume) -> Self {
+        Self { volume: Some(volume), ..self }
+    }
+}
+
+/// Lighting of marched surfaces by any number of lights, with soft shadows
+/// and ambient occlusion both estimated from the distance field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shading {
+    pub lights: Vec<Light>,
+    /// Light arriving from all around, dimmed by the occlusion.
+    pub ambient: f32,
+    /// How `ambient` meets the light of the lamps.
+    pub combine: Ambient,
+    /// Sharpness of the shadow edges, the penumbra narrows as it grows. 0
+    /// casts no shadows.
+    pub penumbra: f32,
+    /// Distance samples along the normal that estimate the occlusion, 0 for none.
+    pub occlusion: usize,
+    /// Spacing of the occlusion samples, about the size of the creases that darken.
+    pub occlusion_step: f32
+}
+
+/// How the ambient light meets the light of the lamps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ambient {
+    /// Added to it.
+    Add,
+    /// A floor under it, as the Lambert term of tinykaboom is clamped to 0.4.
+    Floor
+}
+
+impl FromStr for Ambient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(Ambient::Add),
+            "floor" => Ok(Ambient::Floor),
+            _ => Err(format!("unknown ambient combine '{}': expected add or floor", s))
+        }
+    }
+}
+
+impl fmt::Display for Ambient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Ambient::Add => "add",
+            Ambient::Floor => "floor"
+        })
+    }
+}
+
+impl Default for Shading {
+    /// The tinykaboom light, without shadows or occlusion.
+    fn default() -> Self {
+        Self {
+            lights: vec![Light::new(Vec3f::new(10.0, 10.0, 10.0), 1.0)],
+            ambient: 0.4,
+            combine: Ambient::Floor,
+            penumbra: 0.0,
+            occlusion: 0,
+            occlusion_step: 0.1
+        }
+    }
+}
+
+impl Shading {
+    pub fn with_lights(self, lights: Vec<Light>) -> Self {
+        Self { lights, ..self }
+    }
+
+    /// Light leaving point `p` with normal `n` of a matte surface of the
+    /// given color. Area lights are sampled with `rng`.
+    pub fn shade<S: Sdf + ?Sized>(&self, sdf: &S, tracer: &Tracer, p: Vec3f, n: Vec3f, color: Vec3f, rng: &mut Rng) -> Vec3f {
+        let occlusion = ambient_occlusion(sdf, p, n, self.occlusion, self.occlusion_step);
+        let ambient = self.ambient*occlusion;
+        let mut light = Vec3f::zero();
+        for l in &self.lights {
+            let incident = l.incident(p, rng);
+            let cos = incident.dir*n;
+            if cos <= 0.0 {
+                continue;
+            }
+            let shadow = if self.penumbra > 0.0 {
+                tracer.shadow(sdf, p + n*SHADOW_BIAS, incident.dir, incident.dist, self.penumbra)
+            } else {
+                1.0
+            };
+            light = light + incident.radiance*(cos*shadow);
+        }
+        let light = match self.combine {
+            Ambient::Add => light + Vec3f::one()*ambient,
+            Ambient::Floor => Vec3f::new(light[0].max(ambient), light[1].max(ambient), light[2].max(ambient))
+        };
+        color.hadamard(light)
+    }
+}
+
+/// Share of the ambient light reaching `p`, from how much closer the surface
+/// is than expected at `samples` points along the normal `n`, the nearer ones
+/// weighing more.
+pub fn ambient_occlusion<S: Sdf + ?Sized>(sdf: &S, p: Vec3f, n: Vec3f, samples: usize, step: f32) -> f32 {
+    let (mut occluded, mut total, mut weight) = (0.0, 0.0, 1.0);
+    for i in 1..=samples {
+        let h = step*i as f32;
+        occluded += weight*(h - sdf.dist(p + n*h)).max(0.0);
+        total += weight*h;
+        weight *= 0.5;
+    }
+    if total > 0.0 { (1.0 - occluded/total).clamp(0.0, 1.0) } else { 1.0 }
+}
+
+/// Shape of the explosion at one point in time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Fireball {
+    radius: f32,
+    amplitude: f32,
+    noise: Noise,
+    scroll: Vec3f
+}
+
+impl Fireball {
+    fn at(k: &Kaboom) -> Self {
+        let growth = (1.0 - (-3.0*k.time.max(0.0)).exp())/(1.0 - (-3.0f32).exp());
+        Self {
+            radius: k.radius*growth,
+            amplitude: k.amplitude*growth,
+            noise: k.noise,
+            // Aligned so that the still frame samples the original noise
+            scroll: Vec3f::new(0.0, -SCROLL*(k.time - STILL), 0.0)
+        }
+    }
+}
+
+impl Sdf for Fireball {
+    fn dist(&self, p: Vec3f) -> f32 {
+        let displacement = -self.noise.unit(p + self.scroll) * self.amplitude;
+        p.norm() - (self.radius + displacement)
+    }
+
+    // The coarse one-sided differences smooth the noise, which gives the fireball its look
+    fn normal(&self, pos: Vec3f) -> Vec3f {
+        let eps = 0.1;
+        let d = self.dist(pos);
+        let nx = self.dist(pos + Vec3f::new(eps, 0.0, 0.0)) - d;
+        let ny = self.dist(pos + Vec3f::new(0.0, eps, 0.0)) - d;
+        let nz = self.dist(pos + Vec3f::new(0.0, 0.0, eps)) - d;
+        Vec3f::new(nx,ny,nz).normalize()
+    }
+
+    fn lipschitz(&self) -> f32 {
+        // `unit` halves the slope of `at`
+        1.0 + self.amplitude.abs()*0.5*self.noise.lipschitz()
+    }
+
+    // The noise only pulls the surface in, so outside the sphere its distance is a bound
+    fn bound(&self, p: Vec3f) -> f32 {
+        (p.norm() - self.radius).max(self.dist(p)/self.lipschitz())
+    }
+}
+
+/// Sphere tracer: steps along a ray by the distance bound of the field,
+/// which cannot pass through the surface as long as the bound holds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tracer {
+    /// Field evaluations per ray before giving up, bisection included.
+    pub max_steps: usize,
+    /// Points closer to the surface than this count as hits.
+    pub epsilon: f32,
+    /// Rays that travel further miss.
+    pub max_dist: f32,
+    /// Over-relaxation in 1..2. Steps are stretched by this factor for as
+    /// long as the unbounding spheres of consecutive steps overlap, and fall
+    /// back to plain steps once they do not.
+    pub relaxation: f32,
+    /// Halvings that pin down the surface when a step ended up inside, which
+    /// happens where the field breaks its Lipschitz bound.
+    pub bisection: usize
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self { max_steps: 256, epsilon: 1e-4, max_dist: 1e3, relaxation: 1.6, bisection: 12 }
+    }
 }
 
-fn sphere_trace(orig: Vec3f, dir: Vec3f) -> Option<Vec3f> {
-    if orig*orig - f32::powi(orig*dir, 2) > f32::powi(SPHERE_RADIUS, 2) {
-        return None;
+/// Outcome of tracing one ray.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Trace {
+    /// Distance along the ray to the surface, `None` for a miss.
+    pub hit: Option<f32>,
+    /// Field evaluations it took.
+    pub steps: usize,
+    /// Distance bound at the hit, so how far the surface may be from it.
+    pub error: f32
+}
+
+impl Tracer {
+    /// Traces the part of the ray between `t_min` and `t_max`. A ray starting
+    /// inside is traced to where it leaves.
+    pub fn trace<S: Sdf + ?Sized>(&self, sdf: &S, orig: Vec3f, dir: Vec3f, t_min: f32, t_max: f32) -> Trace {
+        let t_max = t_max.min(self.max_dist);
+        let side = if sdf.dist(orig + dir*t_min) < 0.0 { -1.0 } else { 1.0 };
+        let bound = |t: f32| side*sdf.bound(orig + dir*t);
+        let mut omega = self.relaxation.clamp(1.0, 2.0);
+        let (mut t, mut prev_t, mut prev_r, mut steps) = (t_min, t_min, 0.0, 1);
+        while steps < self.max_steps {
+            let r = bound(t);
+            steps += 1;
+            // A relaxed step that overshot the surface or left a gap is
+            // replaced by the plain step, and relaxation stops for this ray
+            if omega > 1.0 && t > prev_t && (r < 0.0 || r + prev_r < t - prev_t) {
+                t = prev_t + prev_r;
+                omega = 1.0;
+                continue;
+            }
+            if r < 0.0 {
+                let (mut lo, mut hi) = (prev_t, t);
+                for _ in 0..self.bisection.min(self.max_steps - steps) {
+                    let mid = 0.5*(lo + hi);
+                    steps += 1;
+                    if bound(mid) < 0.0 { hi = mid } else { lo = mid }
+                }
+                return Trace { hit: Some(hi), steps, error: hi - lo };
+            }
+            if r < self.epsilon {
+                return Trace { hit: Some(t), steps, error: r };
+            }
+            // Even a plain step would leave the span, so the surface is not in it
+            if t + r > t_max {
+                break;
+            }
+            (prev_t, prev_r) = (t, r);
+            t += r*omega;
+        }
+        Trace { hit: None, steps, error: f32::INFINITY }
     }
 
-    let mut pos = orig;
-    for _ in 0..STEPS {
-        let d = signed_dist(pos);
-        if d < 0.0 {
-            return Some(pos);
+    /// Light let through along the ray up to `t_max`, from 1 where nothing
+    /// comes close to 0 where the ray is blocked. Rays grazing the surface
+    /// are partly shadowed by how close they pass for their length, which
+    /// gives a penumbra of a width set by `penumbra`.
+    pub fn shadow<S: Sdf + ?Sized>(&self, sdf: &S, orig: Vec3f, dir: Vec3f, t_max: f32, penumbra: f32) -> f32 {
+        let t_max = t_max.min(self.max_dist);
+        let (mut light, mut t, mut prev_h) = (1.0f32, 0.0, f32::INFINITY);
+        for _ in 0..self.max_steps {
+            let p = orig + dir*t;
+            // The bound keeps the steps safe, the distance itself measures
+            // how close the ray passes
+            let (r, h) = (sdf.bound(p), sdf.dist(p));
+            if r < self.epsilon {
+                return 0.0;
+            }
+            // Closest approach between this sample and the last, where the
+            // spheres around both intersect. Moving away from the surface
+            // they do not, and the distance here is all there is.
+            let y = h*h/(2.0*prev_h);
+            let (d, along) = if y < h && y < t { ((h*h - y*y).sqrt(), t - y) } else { (h, t) };
+            light = light.min(penumbra*d/along.max(1e-6));
+            prev_h = h;
+            t += r;
+            if t > t_max {
+                break;
+            }
         }
-        pos = pos + dir*f32::max(d*0.1, 0.01);
+        light.clamp(0.0, 1.0)
     }
-    None
 }
 
-pub fn ray_march(dir: Vec3f) -> Vec3f {
-    if let Some(p) = sphere_trace(Vec3f::new(0.0,0.0,3.0), dir) {
-        let noise_lvl = (SPHERE_RADIUS-p.norm())/NOISE_AMP;
-        let light_dir = (Vec3f::new(10.0,10.0,10.0) - p).normalize();
-        let light_intensity = f32::max(0.4, light_dir*distance_field_normal(p));
-        palette((-0.3+noise_lvl)*2.0)*light_intensity
+/// Color of the fireball seen along the ray from `orig` in direction `dir`.
+pub fn ray_march(k: &Kaboom, orig: Vec3f, dir: Vec3f, rng: &mut Rng) -> Vec3f {
+    let ball = Fireball::at(k);
+    // The fireball is modelled around the world origin
+    let orig = orig - k.center;
+    if orig*orig - f32::powi(orig*dir, 2) > f32::powi(ball.radius, 2) {
+        return k.background;
+    }
+    if let Some(volume) = k.volume {
+        return integrate(k, &ball, &volume, orig, dir);
+    }
+    if let Some(t) = k.tracer.trace(&ball, orig, dir, 0.0, f32::INFINITY).hit {
+        let p = orig + dir*t;
+        let noise_lvl = (ball.radius-p.norm())/ball.amplitude.max(1e-3);
+        k.shading.shade(&ball, &k.tracer, p, ball.normal(p), palette((-0.3+noise_lvl)*2.0), rng)
     } else {
-        Vec3f::new(0.2, 0.7, 0.8)
+        k.background
     }
 }
 
-pub fn distance_field_normal(pos: Vec3f) -> Vec3f {
-    let eps = 0.1;
-    let d = signed_dist(pos);
-    let nx = signed_dist(pos + Vec3f::new(eps, 0.0, 0.0)) - d;
-    let ny = signed_dist(pos + Vec3f::new(0.0, eps, 0.0)) - d;
-    let nz = signed_dist(pos + Vec3f::new(0.0, 0.0, eps)) - d;
-    Vec3f::new(nx,ny,nz).normalize()
+/// Emission-absorption integral through the fireball, front to back, with the
+/// background showing through whatever light is not absorbed.
+fn integrate(k: &Kaboom, ball: &Fireball, volume: &Volume, orig: Vec3f, dir: Vec3f) -> Vec3f {
+    // The noise only ever pulls the surface in, so the undisplaced sphere bounds the medium
+    let tca = -(orig*dir);
+    let thc = (ball.radius*ball.radius - (orig*orig - tca*tca)).max(0.0).sqrt();
+    let (t0, t1) = ((tca - thc).max(0.0), tca + thc);
+    let step = volume.step.max(1e-4);
+    let (mut color, mut transmittance) = (Vec3f::zero(), 1.0);
+    let mut t = t0 + step*0.5;
+    while t < t1 && transmittance > 1e-3 {
+        let p = orig + dir*t;
+        let density = (-ball.dist(p)/ball.amplitude.max(1e-3)).clamp(0.0, 1.0);
+        if density > 0.0 {
+            let absorbed = 1.0 - (-volume.extinction*density*step).exp();
+            let noise_lvl = (ball.radius - p.norm())/ball.amplitude.max(1e-3);
+            color = color + palette((-0.3 + noise_lvl)*2.0)*(transmittance*absorbed);
+            transmittance *= 1.0 - absorbed;
+        }
+        t += step;
+    }
+    color + k.background*transmittance
+}
+
+/// Any distance field, marched from the camera and lit like a matte surface.
+pub struct SdfScene {
+    pub sdf: Box<dyn Sdf>,
+    pub color: Vec3f,
+    pub shading: Shading,
+    pub background: Vec3f,
+    pub tracer: Tracer
+}
+
+impl SdfScene {
+    /// A grey shape under one light from above, with soft shadows and
+    /// ambient occlusion, on a dark background.
+    pub fn new<S: Sdf + 'static>(sdf: S) -> Self {
+        Self {
+            sdf: Box::new(sdf),
+            color: Vec3f::one()*0.8,
+            shading: Shading {
+                lights: vec![Light::new(Vec3f::new(-4.0, 8.0, 6.0), 0.9)],
+                ambient: 0.2,
+                combine: Ambient::Add,
+                penumbra: 8.0,
+                occlusion: 5,
+                occlusion_step: 0.1
+            },
+            background: Vec3f::new(0.1, 0.1, 0.12),
+            tracer: Tracer::default()
+        }
+    }
+
+    pub fn with_color(self, color: Vec3f) -> Self {
+        Self { color, ..self }
+    }
+
+    pub fn with_shading(self, shading: Shading) -> Self {
+        Self { shading, ..self }
+    }
+
+    pub fn with_background(self, background: Vec3f) -> Self {
+        Self { background, ..self }
+    }
+
+    pub fn with_tracer(self, tracer: Tracer) -> Self {
+        Self { tracer, ..self }
+    }
+
+    pub fn cast_ray(&self, orig: Vec3f, dir: Vec3f, rng: &mut Rng) -> Vec3f {
+        match self.tracer.trace(&self.sdf, orig, dir, 0.0, f32::INFINITY).hit {
+            Some(t) => {
+                let p = orig + dir*t;
+                self.shading.shade(&self.sdf, &self.tracer, p, self.sdf.normal(p), self.color, rng)
+            },
+            None => self.background
+        }
+    }
 }
 
 pub fn palette(d: f32) -> Vec3f {
@@ -59,4 +457,136 @@ pub fn palette(d: f32) -> Vec3f {
         51..=75 => lerp(red, orange, x*4.0 - 2.0),
         _ => lerp(orange, yellow, x*4.0 - 3.0)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use crate::sdf::{Plane, Sphere, Torus};
+
+    #[test]
+    fn fireball_over_time() {
+        let k = Kaboom::default();
+        let still = Fireball::at(&k);
+        assert_eq!(still, Fireball { radius: k.radius, amplitude: k.amplitude, noise: k.noise, scroll: Vec3f::zero() });
+        let ignition = Fireball::at(&k.clone().with_time(0.0));
+        assert_eq!((ignition.radius, ignition.amplitude), (0.0, 0.0));
+
+        // The fireball keeps growing and its surface keeps moving
+        let later = Fireball::at(&k.clone().with_time(2.0));
+        assert!(later.radius > still.radius && later.amplitude > still.amplitude);
+        let p = Vec3f::new(0.3, 0.9, 0.6);
+        assert_ne!(later.noise.unit(p + later.scroll), still.noise.unit(p + still.scroll));
+        let mut rng = Rng::new(1);
+        let frame = |time: f32, rng: &mut Rng| (0..64)
+            .map(|i| ray_march(&k.clone().with_time(time), Vec3f::zero(), Vec3f::new(i as f32/64.0 - 0.5, 0.1, -1.0).normalize(), rng))
+            .collect::<Vec<_>>();
+        assert_ne!(frame(0.5, &mut rng), frame(1.0, &mut rng));
+    }
+
+    #[test]
+    fn fireball_lipschitz() {
+        let step = Vec3f::new(1e-3, -2e-3, 1e-3);
+        // Not the classic default, which jumps at its cell faces
+        for basis in [Basis::Value, Basis::Perlin, Basis::Simplex, Basis::Worley] {
+            let k = Kaboom { noise: Kaboom::default().noise.with_basis(basis), ..Kaboom::default() };
+            for time in [0.2, STILL, 3.0] {
+                let ball = Fireball::at(&k.clone().with_time(time));
+                let bound = ball.lipschitz()*step.norm();
+                for i in 0..500 {
+                    let p = Vec3f::new((i as f32*0.37).sin(), (i as f32*0.11).cos(), (i as f32*0.23).sin())*1.5;
+                    let change = (ball.dist(p + step) - ball.dist(p)).abs();
+                    assert!(change <= bound + 1e-6, "{} {} {} > {}", basis, time, change, bound);
+                }
+            }
+        }
+    }
+
+    /// Distance that claims to be half as far again as it is.
+    struct Overshoot;
+
+    impl Sdf for Overshoot {
+        fn dist(&self, p: Vec3f) -> f32 {
+            1.5*Sphere::new(2.0).dist(p)
+        }
+    }
+
+    #[test]
+    fn tracer_relaxation_and_bisection() {
+        let orig = Vec3f::new(0.0, 0.0, 5.0);
+        let dir = Vec3f::new(0.0, 0.0, -1.0);
+        // Rays at a shallow angle to a surface take many short steps, relaxation stretches them
+        let up = Vec3f::new(0.0, 1.0, 0.0);
+        let floor = Plane::new(up, 0.0);
+        let grazing = Vec3f::new(0.0, -0.1, -1.0).normalize();
+        let plain = Tracer { relaxation: 1.0, ..Tracer::default() };
+        let (a, b) = (plain.trace(&floor, orig + up, grazing, 0.0, 100.0), Tracer::default().trace(&floor, orig + up, grazing, 0.0, 100.0));
+        assert!((a.hit.unwrap() - b.hit.unwrap()).abs() < 1e-3, "{:?} {:?}", a, b);
+        assert!(b.steps < a.steps, "{} {}", b.steps, a.steps);
+
+        let trace = Tracer::default().trace(&Sphere::new(1.0), orig, dir, 0.0, f32::INFINITY);
+        assert!((trace.hit.unwrap() - 4.0).abs() < 1e-4 && trace.error < 1e-4);
+        assert_eq!(Tracer::default().trace(&Sphere::new(1.0), orig, -dir, 0.0, f32::INFINITY).hit, None);
+
+        // Overshooting steps land inside and bisection recovers the crossing
+        let trace = Tracer::default().trace(&Overshoot, orig, dir, 0.0, f32::INFINITY);
+        assert!((trace.hit.unwrap() - 3.0).abs() <= trace.error + 1e-4, "{:?}", trace);
+        assert!(trace.error < 1e-2);
+    }
+
+    #[test]
+    fn sdf_shadows_and_occlusion() {
+        // A ball resting on the floor under a light straight above it
+        let floor = Plane::new(Vec3f::new(0.0, 1.0, 0.0), 0.0);
+        let ball = Sphere::new(1.0).translate(Vec3f::new(0.0, 1.0, 0.0));
+        let scene = floor.union(ball);
+        let up = Vec3f::new(0.0, 1.0, 0.0);
+        let tracer = Tracer::default();
+        let at = |x: f32| Vec3f::new(x, 0.0, 0.0);
+        let shadow = |x: f32, penumbra: f32| tracer.shadow(&scene, at(x) + up*0.01, up, 10.0, penumbra);
+        assert_eq!(shadow(0.0, 8.0), 0.0);
+        assert_eq!(shadow(3.0, 8.0), 1.0);
+        // Just outside the ball the light is partly covered, less so with sharper edges
+        let edge = shadow(1.1, 8.0);
+        assert!(edge > 0.0 && edge < 1.0 && edge < shadow(1.1, 32.0), "{}", edge);
+        // The floor darkens towards the crease around the ball
+        let crease = ambient_occlusion(&scene, at(0.6), up, 5, 0.1);
+        let open = ambient_occlusion(&scene, at(4.0), up, 5, 0.1);
+        assert!(crease < 0.9 && open > 0.99, "{} {}", crease, open);
+
+        let mut rng = Rng::new(1);
+        let shading = Shading { penumbra: 8.0, occlusion: 5, ..Shading::default() }
+            .with_lights(vec![Light::new(up*10.0, 1.0), Light::new(Vec3f::new(10.0, 10.0, 0.0), 1.0)]);
+        let lit = shading.shade(&scene, &tracer, at(4.0), up, Vec3f::one(), &mut rng);
+        let shaded = shading.shade(&scene, &tracer, at(0.6), up, Vec3f::one(), &mut rng);
+        assert!(lit[0] > 1.5 && shaded[0] < lit[0]*0.5, "{} {}", lit[0], shaded[0]);
+        let dark = Shading { lights: Vec::new(), ..shading };
+        assert!((dark.shade(&scene, &tracer, at(4.0), up, Vec3f::one(), &mut rng)[0] - dark.ambient).abs() < 1e-2);
+    }
+
+    #[test]
+    fn marches_sdf_trees() {
+        // A torus around a sphere, lit from the camera: the ring and the ball show, the gap does not
+        let tree = Torus::new(2.0, 0.4).rotate(Vec3f::new(1.0, 0.0, 0.0), PI/2.0)
+            .union(Sphere::new(1.0))
+            .translate(Vec3f::new(0.0, 0.0, -10.0));
+        let scene = SdfScene::new(tree).with_shading(Shading::default().with_lights(vec![Light::new(Vec3f::zero(), 1.0)]));
+        let at = |x: f32| scene.cast_ray(Vec3f::zero(), Vec3f::new(x, 0.0, -10.0).normalize(), &mut Rng::new(1));
+        assert!((at(0.0) - Vec3f::one()*0.8).norm() < 1e-2);
+        assert!((at(-2.0) - Vec3f::one()*0.8).norm() < 0.1);
+        assert_eq!(at(-1.4), scene.background);
+        assert_eq!(at(-2.6), scene.background);
+    }
+
+    #[test]
+    fn kaboom_volume_is_translucent() {
+        let thin = Kaboom::default().with_volume(Volume { step: 0.05, extinction: 1e-4 });
+        let dense = Kaboom::default().with_volume(Volume { step: 0.05, extinction: 50.0 });
+        let background = Kaboom::default().background;
+        let (ahead, corner) = (Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(-0.77, 0.58, -1.0).normalize());
+        let mut rng = Rng::new(1);
+        assert!((ray_march(&thin, Vec3f::zero(), ahead, &mut rng) - background).norm() < 1e-2);
+        assert!((ray_march(&dense, Vec3f::zero(), ahead, &mut rng) - background).norm() > 0.3);
+        assert_eq!(ray_march(&dense, Vec3f::zero(), corner, &mut rng), background);
+    }
+}
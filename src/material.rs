@@ -0,0 +1,175 @@
+use std::f32::consts::PI;
+
+use crate::geometry::{Vec3f, Vec4f};
+use crate::sampling::Rng;
+use crate::texture::Texture;
+
+/// Reflectance model of a material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    /// tinyraytracer's Phong model, the terms are weighted by `albedo`.
+    Phong,
+    /// Cook-Torrance microfacets with a GGX distribution, Smith shadowing and
+    /// Schlick Fresnel. `diffuse_color` is the base color, `albedo` and
+    /// `specular_exp` are ignored.
+    MetallicRoughness { metallic: f32, roughness: f32 }
+}
+
+#[derive(Clone)]
+pub struct Material {
+    pub diffuse_color: Texture,
+    pub albedo: Vec4f,
+    pub refractive_index: f32,
+    pub specular_exp: f32,
+    /// Absorption coefficient per unit of distance travelled inside, per channel.
+    /// Any non-negative value works, light keeps `exp(-absorption*distance)` of itself.
+    pub absorption: Texture,
+    pub model: Model
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            diffuse_color: Texture::default(),
+            albedo: Vec4f::from([1.0, 0.0, 0.0, 0.0]),
+            refractive_index: 1.0,
+            specular_exp: 0.0,
+            absorption: Texture::default(),
+            model: Model::Phong
+        }
+    }
+}
+
+// Reflectance of common dielectrics at normal incidence
+const DIELECTRIC_F0: f32 = 0.04;
+
+// Below this GGX turns into a delta distribution that point lights cannot hit
+const MIN_ROUGHNESS: f32 = 0.02;
+
+impl Material {
+    pub fn metallic_roughness(base_color: Texture, metallic: f32, roughness: f32) -> Self {
+        Self {
+            diffuse_color: base_color,
+            model: Model::MetallicRoughness { metallic, roughness },
+            ..Self::default()
+        }
+    }
+
+    /// Light reflected from `light_dir` towards the viewer looking along `dir`,
+    /// per unit of incoming light, where the diffuse texture evaluates to `color`.
+    /// Lights follow tinyraytracer's convention, a white Lambertian surface
+    /// facing a light of intensity 1 appears white.
+    pub fn shade(&self, color: Vec3f, n: Vec3f, dir: Vec3f, light_dir: Vec3f) -> Vec3f {
+        let nl = light_dir*n;
+        match self.model {
+            Model::Phong => {
+                let rf = light_dir.reflect(n)*dir;
+                color*(nl.max(0.0)*self.albedo[0])
+                    + Vec3f::one()*(rf.max(0.0).powf(self.specular_exp)*self.albedo[1])
+            },
+            Model::MetallicRoughness { metallic, roughness } => {
+                let v = -dir;
+                let nv = v*n;
+                if nl <= 0.0 || nv <= 0.0 {
+                    return Vec3f::zero();
+                }
+                let h = (v + light_dir).normalize();
+                let alpha = alpha(roughness);
+                let f0 = f0(color, metallic);
+                let f = schlick(f0, v*h);
+                let specular = f*(ggx(n*h, alpha)*smith(nv, nl, alpha)/(4.0*nv*nl));
+                let diffuse = (Vec3f::one() - f).hadamard(color)*((1.0 - metallic)/PI);
+                (diffuse + specular)*(PI*nl)
+            }
+        }
+    }
+}
+
+/// Share of light left after travelling `dist` through a medium with the
+/// given absorption coefficients, following the Beer-Lambert law.
+pub fn transmittance(absorption: Vec3f, dist: f32) -> Vec3f {
+    let t = |a: f32| if a > 0.0 { (-a*dist).exp() } else { 1.0 };
+    Vec3f::new(t(absorption[0]), t(absorption[1]), t(absorption[2]))
+}
+
+/// Reflectance at normal incidence, tinted by the base color for metals.
+pub fn f0(base_color: Vec3f, metallic: f32) -> Vec3f {
+    Vec3f::one()*(DIELECTRIC_F0*(1.0 - metallic)) + base_color*metallic
+}
+
+/// GGX roughness parameter from perceptual roughness.
+pub fn alpha(roughness: f32) -> f32 {
+    let r = roughness.clamp(MIN_ROUGHNESS, 1.0);
+    r*r
+}
+
+/// GGX normal distribution for the cosine `nh` between normal and half vector.
+pub fn ggx(nh: f32, alpha: f32) -> f32 {
+    let a2 = alpha*alpha;
+    let d = nh*nh*(a2 - 1.0) + 1.0;
+    a2/(PI*d*d)
+}
+
+/// Separable Smith masking-shadowing for GGX.
+pub fn smith(nv: f32, nl: f32, alpha: f32) -> f32 {
+    let g1 = |c: f32| 2.0*c/(c + (alpha*alpha + (1.0 - alpha*alpha)*c*c).sqrt());
+    g1(nv)*g1(nl)
+}
+
+pub fn schlick(f0: Vec3f, cos: f32) -> Vec3f {
+    f0 + (Vec3f::one() - f0)*(1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+/// Half vector around `n` distributed as `ggx(n*h)*(n*h)`.
+pub fn sample_ggx(n: Vec3f, alpha: f32, rng: &mut Rng) -> Vec3f {
+    let (u1, u2) = (rng.next_f32(), rng.next_f32());
+    let cos2 = (1.0 - u1)/(1.0 + (alpha*alpha - 1.0)*u1);
+    let (cos, sin) = (cos2.sqrt(), (1.0 - cos2).max(0.0).sqrt());
+    let phi = 2.0*PI*u2;
+    let (t, b) = n.tangents();
+    (t*(sin*phi.cos()) + b*(sin*phi.sin()) + n*cos).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ggx_is_normalized() {
+        // The projected distribution integrates to one over the hemisphere
+        for alpha in [0.1, 0.5, 1.0] {
+            let steps = 20000;
+            let sum: f32 = (0..steps).map(|i| {
+                let theta = (i as f32 + 0.5)/steps as f32*PI/2.0;
+                ggx(theta.cos(), alpha)*theta.cos()*theta.sin()*2.0*PI*(PI/2.0/steps as f32)
+            }).sum();
+            assert!((sum - 1.0).abs() < 1e-2, "{} {}", alpha, sum);
+        }
+    }
+
+    #[test]
+    fn beer_lambert() {
+        let absorption = Vec3f::new(0.5, 0.0, 2.0);
+        let t = transmittance(absorption, 2.0);
+        assert!((t[0] - (-1.0f32).exp()).abs() < 1e-6);
+        assert_eq!(t[1], 1.0);
+        assert_eq!(transmittance(absorption, f32::INFINITY), Vec3f::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn pbr_conserves_energy() {
+        // A white furnace: reflected light of a white dielectric stays below what arrives
+        let m = Material::metallic_roughness(Vec3f::one().into(), 0.0, 0.5);
+        let n = Vec3f::new(0.0, 0.0, 1.0);
+        let dir = Vec3f::new(0.3, 0.0, -1.0).normalize();
+        let mut rng = Rng::new(3);
+        let count = 20000;
+        let mut sum = Vec3f::zero();
+        for _ in 0..count {
+            let l = crate::sampling::cosine_hemisphere(n, &mut rng);
+            // shade() is PI*brdf*cos and the pdf is cos/PI, so shade/cos averages to the albedo
+            sum = sum + m.shade(Vec3f::one(), n, dir, l)*(1.0/(l*n*count as f32));
+        }
+        assert!(sum[0] < 1.0 && sum[0] > 0.8, "{}", sum[0]);
+    }
+}
@@ -0,0 +1,294 @@
+use std::fs::File;
+use std::io::{prelude::*, BufReader, Error, ErrorKind};
+use std::path::Path;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::geometry::{Vec2f, Vec3f};
+use crate::hittable::{Hit, Hittable};
+use crate::material::Material;
+
+const EPSILON: f32 = 1e-7;
+
+/// Möller–Trumbore intersection, returns the distance and the barycentric
+/// coordinates of the hit relative to `v1` and `v2`.
+fn intersect_triangle(orig: Vec3f, dir: Vec3f, v: [Vec3f; 3]) -> Option<(f32, f32, f32)> {
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let p = dir.cross(e2);
+    let det = e1*p;
+    // Scale-aware parallel test, so tiny and huge triangles are treated alike
+    if det.abs() <= EPSILON*e1.norm()*e2.norm() {
+        return None;
+    }
+    let inv_det = 1.0/det;
+    let s = orig - v[0];
+    let u = (s*p)*inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let w = (dir*q)*inv_det;
+    if w < 0.0 || u + w > 1.0 {
+        return None;
+    }
+    let t = (e2*q)*inv_det;
+    if t > EPSILON {
+        Some((t, u, w))
+    } else {
+        None
+    }
+}
+
+fn triangle_bounds(v: [Vec3f; 3]) -> Aabb {
+    Aabb::empty().grow(v[0]).grow(v[1]).grow(v[2])
+}
+
+pub struct Triangle<'a> {
+    pub vertices: [Vec3f; 3],
+    pub material: &'a Material
+}
+
+impl<'a> Triangle<'a> {
+    pub fn new(a: Vec3f, b: Vec3f, c: Vec3f, material: &'a Material) -> Self {
+        Self { vertices: [a, b, c], material }
+    }
+
+    pub fn normal(&self) -> Vec3f {
+        (self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]).normalize()
+    }
+}
+
+impl<'a> Hittable for Triangle<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let (dist, u, v) = intersect_triangle(orig, dir, self.vertices).filter(|h| h.0 < t_max)?;
+        Some(Hit {
+            dist,
+            point: orig + dir*dist,
+            normal: self.normal(),
+            material: self.material,
+            uv: Vec2f::new(u, v)
+        })
+    }
+
+    fn bounds(&self) -> Aabb {
+        triangle_bounds(self.vertices)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>
+}
+
+/// Indexed triangle mesh with per-vertex normals and texture coordinates.
+pub struct Mesh<'a> {
+    positions: Vec<Vec3f>,
+    normals: Vec<Vec3f>,
+    uvs: Vec<Vec2f>,
+    faces: Vec<[Corner; 3]>,
+    material: &'a Material,
+    bvh: Bvh
+}
+
+impl<'a> Mesh<'a> {
+    pub fn load_obj<P: AsRef<Path>>(p: P, material: &'a Material) -> Result<Self, Error> {
+        let file = File::open(p)?;
+        Self::parse_obj(BufReader::new(file), material)
+    }
+
+    /// Reads positions, normals, texture coordinates and faces from Wavefront OBJ.
+    /// Polygons are fan-triangulated, everything else in the file is ignored.
+    /// Vertices without a normal get one averaged from the faces around them.
+    pub fn parse_obj<R: BufRead>(reader: R, material: &'a Material) -> Result<Self, Error> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut faces = Vec::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg));
+            let mut tokens = line.split_whitespace();
+            let floats = |tokens: std::str::SplitWhitespace, count: usize| {
+                let v = tokens.take(count)
+                    .map(|t| t.parse::<f32>().map_err(|_| invalid(&format!("bad number '{}'", t))))
+                    .collect::<Result<Vec<f32>, Error>>()?;
+                if v.len() < count {
+                    return Err(invalid(&format!("expected {} numbers", count)));
+                }
+                Ok(v)
+            };
+            match tokens.next() {
+                Some("v") => {
+                    let v = floats(tokens, 3)?;
+                    positions.push(Vec3f::new(v[0], v[1], v[2]));
+                },
+                Some("vn") => {
+                    let v = floats(tokens, 3)?;
+                    let n = Vec3f::new(v[0], v[1], v[2]);
+                    normals.push(if n.norm() > 0.0 { n.normalize() } else { n });
+                },
+                Some("vt") => {
+                    let v = floats(tokens, 2)?;
+                    uvs.push(Vec2f::new(v[0], v[1]));
+                },
+                Some("f") => {
+                    let corners = tokens
+                        .map(|t| parse_corner(t, positions.len(), uvs.len(), normals.len())
+                            .ok_or_else(|| invalid(&format!("bad face vertex '{}'", t))))
+                        .collect::<Result<Vec<Corner>, Error>>()?;
+                    if corners.len() < 3 {
+                        return Err(invalid("face needs at least 3 vertices"));
+                    }
+                    for i in 1..corners.len() - 1 {
+                        faces.push([corners[0], corners[i], corners[i + 1]]);
+                    }
+                },
+                _ => ()
+            }
+        }
+        Ok(Self::new(positions, normals, uvs, faces, material))
+    }
+
+    fn new(positions: Vec<Vec3f>, mut normals: Vec<Vec3f>, uvs: Vec<Vec2f>,
+           mut faces: Vec<[Corner; 3]>, material: &'a Material) -> Self {
+        // Zero-area faces can never be hit and have no normal to contribute
+        faces.retain(|f| {
+            let [a, b, c] = f.map(|c| positions[c.position]);
+            (b - a).cross(c - a).norm() > 0.0
+        });
+        if faces.iter().flatten().any(|c| c.normal.is_none()) {
+            // Area-weighted vertex normals, appended after the ones from the file
+            let base = normals.len();
+            normals.resize(base + positions.len(), Vec3f::zero());
+            for f in &faces {
+                let [a, b, c] = f.map(|c| positions[c.position]);
+                let n = (b - a).cross(c - a);
+                for c in f {
+                    normals[base + c.position] = normals[base + c.position] + n;
+                }
+            }
+            for n in &mut normals[base..] {
+                if n.norm() > 0.0 {
+                    *n = n.normalize();
+                }
+            }
+            for c in faces.iter_mut().flatten() {
+                c.normal = c.normal.or(Some(base + c.position));
+            }
+        }
+        let bounds: Vec<Aabb> = faces.iter()
+            .map(|f| triangle_bounds(f.map(|c| positions[c.position])))
+            .collect();
+        let bvh = Bvh::new(&bounds);
+        Self { positions, normals, uvs, faces, material, bvh }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.faces.len()
+    }
+}
+
+fn parse_corner(token: &str, positions: usize, uvs: usize, normals: usize) -> Option<Corner> {
+    // OBJ indices are 1-based, negative ones count back from the last element read
+    let index = |s: &str, len: usize| -> Option<usize> {
+        let i: isize = s.parse().ok()?;
+        let i = if i < 0 { len as isize + i } else { i - 1 };
+        if i >= 0 && (i as usize) < len { Some(i as usize) } else { None }
+    };
+    let mut parts = token.split('/');
+    let position = index(parts.next()?, positions)?;
+    let uv = match parts.next() {
+        None | Some("") => None,
+        Some(s) => Some(index(s, uvs)?)
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(s) => Some(index(s, normals)?)
+    };
+    Some(Corner { position, uv, normal })
+}
+
+impl<'a> Hittable for Mesh<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let (dist, face, u, v) = self.bvh.closest(orig, dir, t_max, |i, t| {
+            let f = &self.faces[i];
+            let (d, u, v) = intersect_triangle(orig, dir, f.map(|c| self.positions[c.position]))
+                .filter(|h| h.0 < t)?;
+            Some((d, (d, f, u, v)))
+        })?;
+        let w = 1.0 - u - v;
+        let shading = face.iter()
+            .zip([w, u, v])
+            .fold(Vec3f::zero(), |n, (c, k)| n + self.normals[c.normal.unwrap()]*k);
+        // Vertex normals that are zero or cancel out leave only the face normal
+        let normal = if shading.norm() > 0.0 {
+            shading.normalize()
+        } else {
+            let [a, b, c] = face.map(|c| self.positions[c.position]);
+            (b - a).cross(c - a).normalize()
+        };
+        let uv = match face.map(|c| c.uv) {
+            [Some(a), Some(b), Some(c)] => self.uvs[a]*w + self.uvs[b]*u + self.uvs[c]*v,
+            _ => Vec2f::new(u, v)
+        };
+        Some(Hit { dist, point: orig + dir*dist, normal, material: self.material, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.faces.iter()
+            .flatten()
+            .fold(Aabb::empty(), |b, c| b.grow(self.positions[c.position]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "
+# unit quad in the z = -5 plane
+v -1 -1 -5
+v  1 -1 -5
+v  1  1 -5
+v -1  1 -5
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/4/1
+";
+
+    #[test]
+    fn parse_and_hit_quad() {
+        let material = Material::default();
+        let mesh = Mesh::parse_obj(QUAD.as_bytes(), &material).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+
+        let h = mesh.hit(Vec3f::zero(), Vec3f::new(0.5, 0.5, -5.0).normalize(), f32::MAX).unwrap();
+        assert!((h.point[2] + 5.0).abs() < 1e-5);
+        assert!((h.normal[2] - 1.0).abs() < 1e-5);
+        assert!((h.uv[0] - 0.75).abs() < 1e-5 && (h.uv[1] - 0.75).abs() < 1e-5);
+        assert!(mesh.hit(Vec3f::zero(), Vec3f::new(0.0, 2.0, -5.0).normalize(), f32::MAX).is_none());
+    }
+
+    #[test]
+    fn degenerate_faces_and_normals() {
+        let material = Material::default();
+        // A sliver with a repeated vertex is dropped, a zero normal falls back to the face
+        let obj = "v -1 -1 -5\nv 1 -1 -5\nv 0 1 -5\nvn 0 0 0\nf 1//1 2//1 3//1\nf 1 2 2\n";
+        let mesh = Mesh::parse_obj(obj.as_bytes(), &material).unwrap();
+        assert_eq!(mesh.triangle_count(), 1);
+        let h = mesh.hit(Vec3f::zero(), Vec3f::new(0.0, 0.0, -1.0), f32::MAX).unwrap();
+        assert_eq!(h.normal, Vec3f::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn reports_bad_line() {
+        let material = Material::default();
+        let err = Mesh::parse_obj("v 0 0 0\nf 1 2 3\n".as_bytes(), &material).err().unwrap();
+        assert!(err.to_string().starts_with("line 2"));
+    }
+}
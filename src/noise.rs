@@ -1,4 +1,6 @@
+use std::fmt;
 use std::ops::{Sub, Mul, Add};
+use std::str::FromStr;
 
 use crate::geometry::Vec3f;
 
@@ -9,46 +11,473 @@ where T : Sub<Output = T> + Mul<f32,Output = T> +
     v0 + (v1-v0) * 0.0f32.max(1.0f32.min(t))
 }
 
-#[inline]
-fn hash(n: f32) -> f32 {
-    let x = f32::sin(n)*43758.5453;
+/// Lattice noise function underlying a `Noise`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Basis {
+    /// Random values at lattice points, smoothly interpolated.
+    Value,
+    /// Random gradients at lattice points.
+    Perlin,
+    /// Gradients on a simplex grid, fewer directional artifacts than Perlin.
+    Simplex,
+    /// Distance to the nearest of randomly scattered feature points.
+    Worley,
+    /// The value noise of tinykaboom, on a rotated lattice with a sine hash.
+    /// Its blend weights mix the axes, so it jumps at cell faces and
+    /// `Noise::lipschitz` only holds within cells. It ignores the seed.
+    Classic
+}
+
+/// How octaves are combined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fractal {
+    /// Plain sum, fractal Brownian motion.
+    Fbm,
+    /// Inverted absolute value, sharp ridges along the zero crossings.
+    Ridged,
+    /// Absolute value, rounded bumps with creases in between.
+    Billow
+}
+
+impl FromStr for Basis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "value" => Ok(Basis::Value),
+            "perlin" => Ok(Basis::Perlin),
+            "simplex" => Ok(Basis::Simplex),
+            "worley" => Ok(Basis::Worley),
+            "classic" => Ok(Basis::Classic),
+            _ => Err(format!("unknown noise basis '{}': expected value, perlin, simplex, worley or classic", s))
+        }
+    }
+}
+
+impl fmt::Display for Basis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Basis::Value => "value",
+            Basis::Perlin => "perlin",
+            Basis::Simplex => "simplex",
+            Basis::Worley => "worley",
+            Basis::Classic => "classic"
+        })
+    }
+}
+
+impl FromStr for Fractal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fbm" => Ok(Fractal::Fbm),
+            "ridged" => Ok(Fractal::Ridged),
+            "billow" => Ok(Fractal::Billow),
+            _ => Err(format!("unknown fractal '{}': expected fbm, ridged or billow", s))
+        }
+    }
+}
+
+impl fmt::Display for Fractal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Fractal::Fbm => "fbm",
+            Fractal::Ridged => "ridged",
+            Fractal::Billow => "billow"
+        })
+    }
+}
+
+/// Seeded fractal noise. The same seed and settings always give the same
+/// values, on any thread and platform.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Noise {
+    pub seed: u32,
+    pub basis: Basis,
+    pub fractal: Fractal,
+    pub octaves: usize,
+    /// Frequency multiplier from one octave to the next. The classic basis
+    /// keeps the uneven steps of tinykaboom, 2.32, 3.03 and 2.61 at the
+    /// default of 2, scaled along with it.
+    pub lacunarity: f32,
+    /// Amplitude multiplier from one octave to the next.
+    pub gain: f32,
+    /// Frequency of the first octave.
+    pub frequency: f32
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            basis: Basis::Perlin,
+            fractal: Fractal::Fbm,
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+            frequency: 1.0
+        }
+    }
+
+    pub fn with_basis(self, basis: Basis) -> Self {
+        Self { basis, ..self }
+    }
+
+    pub fn with_fractal(self, fractal: Fractal) -> Self {
+        Self { fractal, ..self }
+    }
+
+    pub fn with_octaves(self, octaves: usize) -> Self {
+        Self { octaves: octaves.max(1), ..self }
+    }
+
+    pub fn with_lacunarity(self, lacunarity: f32) -> Self {
+        Self { lacunarity, ..self }
+    }
+
+    pub fn with_gain(self, gain: f32) -> Self {
+        Self { gain, ..self }
+    }
+
+    pub fn with_frequency(self, frequency: f32) -> Self {
+        Self { frequency, ..self }
+    }
+
+    /// Single octave of the basis at `p`, in -1..1. Every octave gets its own seed.
+    pub fn basis_at(&self, p: Vec3f, seed: u32) -> f32 {
+        match self.basis {
+            Basis::Value => value(p, seed),
+            Basis::Perlin => perlin(p, seed),
+            Basis::Simplex => simplex(p, seed),
+            Basis::Worley => worley(p, seed),
+            Basis::Classic => classic(p)
+        }
+    }
+
+    /// Frequency multiplier from `octave` to the next.
+    fn step(&self, octave: usize) -> f32 {
+        match self.basis {
+            Basis::Classic => CLASSIC_STEPS[octave % CLASSIC_STEPS.len()]*self.lacunarity/2.0,
+            _ => self.lacunarity
+        }
+    }
+
+    /// Fractal noise at `p`, in -1..1.
+    pub fn at(&self, p: Vec3f) -> f32 {
+        let mut p = p*self.frequency;
+        let (mut sum, mut amp, mut total) = (0.0, 1.0, 0.0);
+        for octave in 0..self.octaves.max(1) {
+            let n = self.basis_at(p, self.seed.wrapping_add((octave as u32).wrapping_mul(0x9E37_79B9)));
+            sum += amp*match self.fractal {
+                Fractal::Fbm => n,
+                Fractal::Ridged => {
+                    let r = 1.0 - n.abs();
+                    2.0*r*r - 1.0
+                },
+                Fractal::Billow => 2.0*n.abs() - 1.0
+            };
+            total += amp;
+            amp *= self.gain;
+            p = p*self.step(octave);
+        }
+        if total > 0.0 { sum/total } else { 0.0 }
+    }
+
+    /// Fractal noise remapped to 0..1.
+    pub fn unit(&self, p: Vec3f) -> f32 {
+        0.5 + 0.5*self.at(p)
+    }
+
+    /// Upper bound on how fast `at` changes per unit of distance.
+    pub fn lipschitz(&self) -> f32 {
+        // Steepest slopes of one octave, measured and rounded up
+        let basis = match self.basis {
+            Basis::Value => 4.0,
+            Basis::Perlin => 3.0,
+            Basis::Simplex => 7.5,
+            Basis::Worley => 2.0,
+            Basis::Classic => 16.0
+        };
+        let fold = match self.fractal {
+            Fractal::Fbm => 1.0,
+            Fractal::Ridged => 4.0,
+            Fractal::Billow => 2.0
+        };
+        let (mut sum, mut amp, mut freq, mut total) = (0.0, 1.0, 1.0, 0.0);
+        for octave in 0..self.octaves.max(1) {
+            sum += amp*freq;
+            total += amp;
+            amp *= self.gain;
+            freq *= self.step(octave);
+        }
+        if total > 0.0 { basis*fold*self.frequency.abs()*sum/total } else { 0.0 }
+    }
+}
+
+fn hash3(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x8DA6_B343)
+        ^ (y as u32).wrapping_mul(0xD816_3841)
+        ^ (z as u32).wrapping_mul(0xCB1A_B31F);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^ (h >> 16)
+}
+
+/// Hash to -1..1.
+fn signed(h: u32) -> f32 {
+    (h >> 8) as f32/(1u32 << 23) as f32 - 1.0
+}
+
+fn lattice(p: Vec3f) -> ([i32; 3], Vec3f) {
+    let f = Vec3f::new(p[0].floor(), p[1].floor(), p[2].floor());
+    ([f[0] as i32, f[1] as i32, f[2] as i32], p - f)
+}
+
+fn quintic(t: f32) -> f32 {
+    t*t*t*(t*(t*6.0 - 15.0) + 10.0)
+}
+
+/// Trilinear blend of the 8 corner values, `corner` gets offsets in 0..=1.
+fn blend<F: Fn(i32, i32, i32) -> f32>(f: Vec3f, corner: F) -> f32 {
+    let (u, v, w) = (quintic(f[0]), quintic(f[1]), quintic(f[2]));
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), u);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), u);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), u);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), u);
+    lerp(lerp(x00, x10, v), lerp(x01, x11, v), w)
+}
+
+fn value(p: Vec3f, seed: u32) -> f32 {
+    let ([x, y, z], f) = lattice(p);
+    blend(f, |i, j, k| signed(hash3(x + i, y + j, z + k, seed)))
+}
+
+// Edge midpoints of a cube, Perlin's improved noise gradient set
+const GRADIENTS: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0]
+];
+
+fn gradient(h: u32, d: Vec3f) -> f32 {
+    let g = GRADIENTS[(h % 12) as usize];
+    g[0]*d[0] + g[1]*d[1] + g[2]*d[2]
+}
+
+// Largest value of `blend`ed gradients from `GRADIENTS`, about 1.0364, found by
+// maximizing over the point and the 8 corner gradients
+const PERLIN_PEAK: f32 = 1.0364;
+
+fn perlin(p: Vec3f, seed: u32) -> f32 {
+    let ([x, y, z], f) = lattice(p);
+    blend(f, |i, j, k| {
+        let d = f - Vec3f::new(i as f32, j as f32, k as f32);
+        gradient(hash3(x + i, y + j, z + k, seed), d)
+    })/PERLIN_PEAK
+}
+
+// Largest sum of the simplex kernels with gradients from `GRADIENTS`, about
+// 0.013007, found the same way as `PERLIN_PEAK`
+const SIMPLEX_PEAK: f32 = 0.01301;
+
+fn simplex(p: Vec3f, seed: u32) -> f32 {
+    const F3: f32 = 1.0/3.0;
+    const G3: f32 = 1.0/6.0;
+    // Skew into the simplex lattice and find the containing tetrahedron
+    let s = (p[0] + p[1] + p[2])*F3;
+    let ([i, j, k], _) = lattice(p + Vec3f::one()*s);
+    let t = (i + j + k) as f32*G3;
+    let d0 = p - Vec3f::new(i as f32 - t, j as f32 - t, k as f32 - t);
+    let (x, y, z) = (d0[0], d0[1], d0[2]);
+    let (o1, o2) = if x >= y {
+        if y >= z { ([1, 0, 0], [1, 1, 0]) }
+        else if x >= z { ([1, 0, 0], [1, 0, 1]) }
+        else { ([0, 0, 1], [1, 0, 1]) }
+    } else if y < z { ([0, 0, 1], [0, 1, 1]) }
+    else if x < z { ([0, 1, 0], [0, 1, 1]) }
+    else { ([0, 1, 0], [1, 1, 0]) };
+    let corners = [[0, 0, 0], o1, o2, [1, 1, 1]];
+    let mut sum = 0.0;
+    for (n, c) in corners.iter().enumerate() {
+        let d = d0 - Vec3f::new(c[0] as f32, c[1] as f32, c[2] as f32) + Vec3f::one()*(n as f32*G3);
+        // A radius of sqrt(1/2) ends every kernel at the far face of the simplex
+        let falloff = 0.5 - d*d;
+        if falloff > 0.0 {
+            let f2 = falloff*falloff;
+            sum += f2*f2*gradient(hash3(i + c[0], j + c[1], k + c[2], seed), d);
+        }
+    }
+    sum/SIMPLEX_PEAK
+}
+
+const CLASSIC_STEPS: [f32; 3] = [2.32, 3.03, 2.61];
+
+fn classic_hash(n: f32) -> f32 {
+    let x = f32::sin(n)*43758.547;
     x - x.floor()
 }
 
-#[inline]
-fn noise(x: Vec3f) -> f32 {
+fn classic(p: Vec3f) -> f32 {
+    let x = Vec3f::new(
+        Vec3f::new(0.00,  0.80, 0.60)*p,
+        Vec3f::new(-0.80, 0.36, -0.48)*p,
+        Vec3f::new(-0.60, -0.48, 0.64)*p
+    );
     let p = Vec3f::new(x[0].floor(), x[1].floor(), x[2].floor());
-    let mut f = x-p;
+    let mut f = x - p;
     f = f*(f*(Vec3f::one()*3.0 - f*2.0));
     let n = p*Vec3f::new(1.0, 57.0, 113.0);
-    lerp(lerp(
-        lerp(hash(n + 0.0 ), hash(n + 1.0 ), f[0]),
-        lerp(hash(n + 57.0), hash(n + 58.0), f[0]), 
-        f[1]),
-        lerp(
-            lerp(hash(n + 113.0), hash(n + 114.0), f[0]),
-            lerp(hash(n + 170.0), hash(n + 171.0), f[0]), 
-            f[1]), 
-            f[2]
-        )
-    }
-    
-    fn rotate(v: Vec3f) -> Vec3f {
-        Vec3f::new(
-            Vec3f::new(0.00,  0.80, 0.60)*v, 
-            Vec3f::new(-0.80, 0.36, -0.48)*v,
-            Vec3f::new(-0.60, -0.48,0.64)*v
-        )
-    }
-    
-    #[inline]
-    pub fn fractal_brownian_motion(x: Vec3f) -> f32 {
-        let mut p = rotate(x);
-        let mut f = 0.0;
-        f += 0.5000*noise(p); p = p*2.32;
-        f += 0.2500*noise(p); p = p*3.03;
-        f += 0.1250*noise(p); p = p*2.61;
-        f += 0.0625*noise(p);
-        f/0.9375
-    }
-    
\ No newline at end of file
+    let h = |o: f32| classic_hash(n + o);
+    let v = lerp(
+        lerp(lerp(h(0.0), h(1.0), f[0]), lerp(h(57.0), h(58.0), f[0]), f[1]),
+        lerp(lerp(h(113.0), h(114.0), f[0]), lerp(h(170.0), h(171.0), f[0]), f[1]),
+        f[2]
+    );
+    2.0*v - 1.0
+}
+
+fn worley(p: Vec3f, seed: u32) -> f32 {
+    let ([x, y, z], f) = lattice(p);
+    let mut nearest = f32::MAX;
+    for i in -1..=1 {
+        for j in -1..=1 {
+            for k in -1..=1 {
+                let h = hash3(x + i, y + j, z + k, seed);
+                let feature = Vec3f::new(
+                    i as f32 + (h & 0x3FF) as f32/1024.0,
+                    j as f32 + ((h >> 10) & 0x3FF) as f32/1024.0,
+                    k as f32 + ((h >> 20) & 0x3FF) as f32/1024.0
+                );
+                let d = feature - f;
+                nearest = nearest.min(d*d);
+            }
+        }
+    }
+    // Distances to the nearest point rarely exceed one cell
+    2.0*nearest.sqrt().min(1.0) - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASES: [Basis; 5] = [Basis::Value, Basis::Perlin, Basis::Simplex, Basis::Worley, Basis::Classic];
+    const FRACTALS: [Fractal; 3] = [Fractal::Fbm, Fractal::Ridged, Fractal::Billow];
+
+    fn points() -> impl Iterator<Item = Vec3f> {
+        (0..500).map(|i| Vec3f::new(i as f32*0.731 - 100.0, (i*i % 97) as f32*0.377, i as f32*-0.219))
+    }
+
+    #[test]
+    fn deterministic() {
+        for basis in BASES {
+            let a = Noise::new(7).with_basis(basis);
+            let b = Noise::new(7).with_basis(basis);
+            let c = Noise::new(8).with_basis(basis);
+            assert!(points().all(|p| a.at(p) == b.at(p)), "{}", basis);
+            assert_eq!(points().any(|p| a.at(p) != c.at(p)), basis != Basis::Classic, "{}", basis);
+        }
+    }
+
+    #[test]
+    fn basis_range() {
+        // Every basis stays in -1..1 by itself, dense samples over many seeds come close to both ends
+        let bases: [fn(Vec3f, u32) -> f32; 5] = [value, perlin, simplex, worley, |p, _| classic(p)];
+        for (basis, f) in BASES.iter().zip(bases) {
+            let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+            for seed in 0..16 {
+                for i in 0..2000 {
+                    let p = Vec3f::new(i as f32*0.1237, (i*i % 1013) as f32*0.0371, (i % 89) as f32*0.0917);
+                    let v = f(p, seed);
+                    lo = lo.min(v);
+                    hi = hi.max(v);
+                }
+            }
+            assert!(-1.0 <= lo && hi <= 1.0, "{} {}..{}", basis, lo, hi);
+            assert!(lo < -0.8 && hi > 0.8, "{} {}..{}", basis, lo, hi);
+        }
+    }
+
+    #[test]
+    fn output_range() {
+        for basis in BASES {
+            for fractal in FRACTALS {
+                let noise = Noise::new(3).with_basis(basis).with_fractal(fractal).with_octaves(5).with_frequency(1.7);
+                let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+                for p in points() {
+                    let v = noise.at(p);
+                    assert!((-1.0..=1.0).contains(&v), "{} {} {}", basis, fractal, v);
+                    lo = lo.min(v);
+                    hi = hi.max(v);
+                }
+                // The noise should actually vary, not sit at a constant
+                assert!(hi - lo > 0.2, "{} {} {}..{}", basis, fractal, lo, hi);
+            }
+        }
+    }
+
+    // The classic basis jumps at its cell faces
+    fn continuous() -> impl Iterator<Item = Basis> {
+        BASES.into_iter().filter(|&b| b != Basis::Classic)
+    }
+
+    #[test]
+    fn lipschitz_bound() {
+        let step = Vec3f::new(1e-3, -2e-3, 1e-3);
+        for basis in continuous() {
+            for fractal in FRACTALS {
+                let noise = Noise::new(5).with_basis(basis).with_fractal(fractal).with_frequency(2.0);
+                let bound = noise.lipschitz()*step.norm();
+                for p in points() {
+                    let change = (noise.at(p + step) - noise.at(p)).abs();
+                    assert!(change <= bound, "{} {} {} > {}", basis, fractal, change, bound);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_jumps() {
+        // Pairs of points a tiny step apart catch jumps that sparse samples miss
+        let h = 1e-4;
+        for basis in continuous() {
+            let noise = Noise::new(11).with_basis(basis).with_octaves(1);
+            let bound = noise.lipschitz()*h;
+            for i in 0..20000 {
+                let t = i as f32;
+                let p = Vec3f::new((t*0.618_034).fract(), (t*0.754_878).fract(), (t*0.569_840).fract())*8.0;
+                let dir = Vec3f::new(t.sin(), (t*1.3).cos(), (t*0.7).sin()).normalize();
+                let change = (noise.at(p + dir*h) - noise.at(p)).abs();
+                assert!(change <= bound + 1e-5, "{} {:?} {} > {}", basis, p, change, bound);
+            }
+        }
+    }
+
+    #[test]
+    fn classic_octaves() {
+        // The tinykaboom sum, its octaves stepping by 2.32, 3.03 and 2.61
+        let noise = Noise::new(0).with_basis(Basis::Classic).with_frequency(3.4);
+        for p in points().map(|p| p*(1.0/64.0)) {
+            let octave = |scale: f32| 0.5 + 0.5*classic(p*(3.4*scale));
+            let expected = (0.5*octave(1.0) + 0.25*octave(2.32) + 0.125*octave(2.32*3.03) + 0.0625*octave(2.32*3.03*2.61))/0.9375;
+            assert!((noise.unit(p) - expected).abs() < 1e-5, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn gradient_noise_vanishes_on_lattice() {
+        for seed in 0..4 {
+            let noise = Noise::new(seed);
+            assert_eq!(noise.basis_at(Vec3f::new(3.0, -2.0, 5.0), seed), 0.0);
+        }
+    }
+}
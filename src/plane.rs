@@ -0,0 +1,192 @@
+use std::f32::consts::PI;
+
+use crate::bvh::Aabb;
+use crate::geometry::{Vec2f, Vec3f};
+use crate::hittable::{Hit, Hittable};
+use crate::material::Material;
+
+const PARALLEL_EPS: f32 = 1e-6;
+
+fn plane_intersect(orig: Vec3f, dir: Vec3f, point: Vec3f, normal: Vec3f) -> Option<f32> {
+    let denom = dir*normal;
+    if denom.abs() < PARALLEL_EPS {
+        return None;
+    }
+    let d = ((point - orig)*normal)/denom;
+    if d > 0.0 { Some(d) } else { None }
+}
+
+pub struct Plane<'a> {
+    pub point: Vec3f,
+    pub normal: Vec3f,
+    pub material: &'a Material
+}
+
+impl<'a> Plane<'a> {
+    /// Panics if the normal is zero.
+    pub fn new(point: Vec3f, normal: Vec3f, material: &'a Material) -> Self {
+        assert!(normal.norm() > 0.0, "plane normal must be non-zero");
+        Self { point, normal: normal.normalize(), material }
+    }
+}
+
+impl<'a> Hittable for Plane<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let dist = plane_intersect(orig, dir, self.point, self.normal).filter(|&d| d < t_max)?;
+        let point = orig + dir*dist;
+        let (t, b) = self.normal.tangents();
+        let local = point - self.point;
+        Some(Hit { dist, point, normal: self.normal, material: self.material, uv: Vec2f::new(local*t, local*b) })
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::new(Vec3f::one()*f32::NEG_INFINITY, Vec3f::one()*f32::INFINITY)
+    }
+}
+
+/// Parallelogram spanned by `edge_u` and `edge_v` from `corner`.
+/// The normal follows `edge_u x edge_v`, UV runs from 0 to 1 along the edges.
+pub struct Rect<'a> {
+    pub corner: Vec3f,
+    pub edge_u: Vec3f,
+    pub edge_v: Vec3f,
+    pub material: &'a Material
+}
+
+impl<'a> Rect<'a> {
+    /// Panics if the edges are zero or parallel, which leaves no plane to hit.
+    pub fn new(corner: Vec3f, edge_u: Vec3f, edge_v: Vec3f, material: &'a Material) -> Self {
+        assert!(edge_u.cross(edge_v).norm() > 0.0, "rect edges must be non-zero and not parallel");
+        Self { corner, edge_u, edge_v, material }
+    }
+
+    pub fn normal(&self) -> Vec3f {
+        self.edge_u.cross(self.edge_v).normalize()
+    }
+}
+
+impl<'a> Hittable for Rect<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let normal = self.normal();
+        let dist = plane_intersect(orig, dir, self.corner, normal).filter(|&d| d < t_max)?;
+        let point = orig + dir*dist;
+        // Dual basis, so that non-orthogonal edges still give exact coordinates
+        let w = self.edge_u.cross(self.edge_v);
+        let w = w*(1.0/(w*w));
+        let local = point - self.corner;
+        let u = w*local.cross(self.edge_v);
+        let v = w*self.edge_u.cross(local);
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(Hit { dist, point, normal, material: self.material, uv: Vec2f::new(u, v) })
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::empty()
+            .grow(self.corner)
+            .grow(self.corner + self.edge_u)
+            .grow(self.corner + self.edge_v)
+            .grow(self.corner + self.edge_u + self.edge_v)
+    }
+}
+
+pub struct Disk<'a> {
+    pub center: Vec3f,
+    pub normal: Vec3f,
+    pub radius: f32,
+    pub material: &'a Material
+}
+
+impl<'a> Disk<'a> {
+    /// Panics if the normal is zero.
+    pub fn new(center: Vec3f, normal: Vec3f, radius: f32, material: &'a Material) -> Self {
+        assert!(normal.norm() > 0.0, "disk normal must be non-zero");
+        Self { center, normal: normal.normalize(), radius, material }
+    }
+}
+
+impl<'a> Hittable for Disk<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let dist = plane_intersect(orig, dir, self.center, self.normal).filter(|&d| d < t_max)?;
+        let point = orig + dir*dist;
+        let local = point - self.center;
+        let r = local.norm();
+        if r > self.radius {
+            return None;
+        }
+        let (t, b) = self.normal.tangents();
+        let uv = Vec2f::new(r/self.radius, 0.5 + f32::atan2(local*b, local*t)/(2.0*PI));
+        Some(Hit { dist, point, normal: self.normal, material: self.material, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let n = self.normal;
+        let e = Vec3f::new(
+            (1.0 - n[0]*n[0]).max(0.0).sqrt(),
+            (1.0 - n[1]*n[1]).max(0.0).sqrt(),
+            (1.0 - n[2]*n[2]).max(0.0).sqrt()
+        )*self.radius;
+        Aabb::new(self.center - e, self.center + e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec2f, b: Vec2f) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    #[test]
+    fn plane_rect_and_disk() {
+        let m = Material::default();
+        let down = Vec3f::new(0.0, -1.0, 0.0);
+        let up = Vec3f::new(0.0, 1.0, 0.0);
+        let plane = Plane::new(Vec3f::new(0.0, -2.0, 0.0), up*3.0, &m);
+        let h = plane.hit(Vec3f::new(1.0, 0.0, 0.0), down, f32::MAX).unwrap();
+        assert_eq!((h.dist, h.normal), (2.0, up));
+        let (t, b) = up.tangents();
+        assert!(close(h.uv, Vec2f::new(t[0], b[0])));
+        assert!(plane.hit(Vec3f::zero(), up, f32::MAX).is_none());
+        assert!(plane.hit(Vec3f::zero(), Vec3f::new(1.0, 0.0, 0.0), f32::MAX).is_none());
+
+        // Slanted edges still give coordinates along them
+        let rect = Rect::new(Vec3f::new(0.0, -1.0, 0.0), Vec3f::new(2.0, 0.0, 0.0), Vec3f::new(1.0, 0.0, -1.0), &m);
+        let h = rect.hit(Vec3f::new(1.5, 0.0, -0.5), down, f32::MAX).unwrap();
+        assert_eq!(h.normal, up);
+        assert!(close(h.uv, Vec2f::new(0.5, 0.5)));
+        assert!(rect.hit(Vec3f::new(-0.1, 0.0, -0.05), down, f32::MAX).is_none());
+        assert!(rect.hit(Vec3f::new(1.5, 0.0, -0.5), down, 0.5).is_none());
+
+        let disk = Disk::new(Vec3f::new(0.0, -1.0, 0.0), up, 2.0, &m);
+        let h = disk.hit(Vec3f::new(1.0, 0.0, 0.0), down, f32::MAX).unwrap();
+        assert_eq!(h.uv[0], 0.5);
+        let h2 = disk.hit(Vec3f::new(-1.0, 0.0, 0.0), down, f32::MAX).unwrap();
+        // Opposite sides are half a turn apart
+        assert!(((h.uv[1] - h2.uv[1]).abs() - 0.5).abs() < 1e-5);
+        assert!(disk.hit(Vec3f::new(1.5, 0.0, 1.5), down, f32::MAX).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn degenerate_rect() {
+        let m = Material::default();
+        Rect::new(Vec3f::zero(), Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(-2.0, 0.0, 0.0), &m);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_plane_normal() {
+        let m = Material::default();
+        Plane::new(Vec3f::zero(), Vec3f::zero(), &m);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_disk_normal() {
+        let m = Material::default();
+        Disk::new(Vec3f::zero(), Vec3f::zero(), 1.0, &m);
+    }
+}
@@ -0,0 +1,349 @@
+use std::io::{prelude::*, Error};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the longest run that cannot overflow `b` before reducing
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    n: u32
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { out: Vec::new(), acc: 0, n: 0 }
+    }
+
+    /// Appends the low `n` bits of `value`, least significant first.
+    fn bits(&mut self, value: u32, n: u32) {
+        self.acc |= value << self.n;
+        self.n += n;
+        while self.n >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    /// Huffman codes are defined most significant bit first.
+    fn code(&mut self, code: u32, n: u32) {
+        self.bits(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn align(&mut self) {
+        if self.n > 0 {
+            self.bits(0, 8 - self.n);
+        }
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/// Symbol of the fixed literal/length alphabet.
+fn literal(w: &mut BitWriter, sym: u32) {
+    match sym {
+        0..=143 => w.code(0x30 + sym, 8),
+        144..=255 => w.code(0x190 + sym - 144, 9),
+        256..=279 => w.code(sym - 256, 7),
+        _ => w.code(0xc0 + sym - 280, 8)
+    }
+}
+
+fn emit_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let l = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    literal(w, 257 + l as u32);
+    w.bits((len - LENGTH_BASE[l] as usize) as u32, LENGTH_EXTRA[l] as u32);
+    let d = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.code(d as u32, 5);
+    w.bits((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+/// Hash chains over 3-byte prefixes of the last `WINDOW` bytes.
+struct Matcher {
+    head: Vec<usize>,
+    prev: Vec<usize>
+}
+
+impl Matcher {
+    fn new() -> Self {
+        Self { head: vec![usize::MAX; 1 << HASH_BITS], prev: vec![usize::MAX; WINDOW] }
+    }
+
+    fn hash(data: &[u8], i: usize) -> usize {
+        let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH <= data.len() {
+            let h = Self::hash(data, i);
+            self.prev[i % WINDOW] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    /// Longest earlier match for the bytes at `i`, as (length, distance).
+    fn longest(&self, data: &[u8], i: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if i + MIN_MATCH > data.len() {
+            return best;
+        }
+        let max = (data.len() - i).min(MAX_MATCH);
+        let mut cand = self.head[Self::hash(data, i)];
+        for _ in 0..MAX_CHAIN {
+            if cand == usize::MAX || i - cand > WINDOW {
+                break;
+            }
+            let len = data[cand..cand + max].iter().zip(&data[i..i + max]).take_while(|(a, b)| a == b).count();
+            if len > best.0 {
+                best = (len, i - cand);
+                if len == max {
+                    break;
+                }
+            }
+            // Slots are reused once the window slides, a newer entry ends the chain
+            let next = self.prev[cand % WINDOW];
+            if next >= cand {
+                break;
+            }
+            cand = next;
+        }
+        best
+    }
+}
+
+/// Single fixed-Huffman block with greedy LZ77 matching.
+fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.bits(1, 1);
+    w.bits(1, 2);
+    let mut matcher = Matcher::new();
+    let mut i = 0;
+    while i < data.len() {
+        let (len, dist) = matcher.longest(data, i);
+        if len >= MIN_MATCH {
+            emit_match(&mut w, len, dist);
+            for k in i..i + len {
+                matcher.insert(data, k);
+            }
+            i += len;
+        } else {
+            literal(&mut w, data[i] as u32);
+            matcher.insert(data, i);
+            i += 1;
+        }
+    }
+    literal(&mut w, 256);
+    w.align();
+    w.out
+}
+
+fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len()/65535*5 + 5);
+    let mut chunks = data.chunks(65535).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(if chunks.peek().is_none() { 1 } else { 0 });
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// zlib stream of `data`, using whichever of a fixed-Huffman or a stored block is smaller.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let fixed = deflate_fixed(data);
+    let body = if fixed.len() < data.len() + 5 { fixed } else { deflate_stored(data) };
+    let mut out = Vec::with_capacity(body.len() + 6);
+    out.extend_from_slice(&[0x78, 0x01]);
+    out.extend_from_slice(&body);
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), Error> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body = Vec::with_capacity(data.len() + 4);
+    body.extend_from_slice(kind);
+    body.extend_from_slice(data);
+    w.write_all(&body)?;
+    w.write_all(&crc32(&body).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Writes 8-bit RGB pixels as a PNG. Each scanline gets the filter with the
+/// smallest sum of absolute residuals, the usual heuristic for photos.
+pub fn write_rgb<W: Write>(w: &mut W, width: usize, height: usize, rgb: &[u8]) -> Result<(), Error> {
+    let stride = width*3;
+    let mut raw = Vec::with_capacity((stride + 1)*height);
+    let zero = vec![0u8; stride];
+    for y in 0..height {
+        let row = &rgb[y*stride..(y + 1)*stride];
+        let up = if y > 0 { &rgb[(y - 1)*stride..y*stride] } else { &zero[..] };
+        let left = |x: usize| if x >= 3 { row[x - 3] } else { 0 };
+        let up_left = |x: usize| if x >= 3 { up[x - 3] } else { 0 };
+        let filtered: Vec<Vec<u8>> = (0..5u8).map(|f| (0..stride).map(|x| {
+            let pred = match f {
+                0 => 0,
+                1 => left(x),
+                2 => up[x],
+                3 => ((left(x) as u16 + up[x] as u16)/2) as u8,
+                _ => paeth(left(x), up[x], up_left(x))
+            };
+            row[x].wrapping_sub(pred)
+        }).collect()).collect();
+        let cost = |r: &Vec<u8>| r.iter().map(|&v| (v as i8).unsigned_abs() as u32).sum::<u32>();
+        let best = (0..5).min_by_key(|&f| cost(&filtered[f])).unwrap();
+        raw.push(best as u8);
+        raw.extend_from_slice(&filtered[best]);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    w.write_all(&SIGNATURE)?;
+    chunk(w, b"IHDR", &header)?;
+    chunk(w, b"IDAT", &zlib(&raw))?;
+    chunk(w, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    /// Inflates a single fixed-Huffman block, enough to read back `deflate_fixed`.
+    fn inflate_fixed(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut bits = |n: u32| (0..n).fold(0, |v, k| {
+            let b = (data[pos/8] >> (pos % 8)) & 1;
+            pos += 1;
+            v | (b as u32) << k
+        });
+        assert_eq!(bits(3), 0b011, "final fixed-Huffman block");
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            // Codes are read most significant bit first, 7 to 9 bits long
+            let mut code = 0;
+            let mut len = 0;
+            let sym = loop {
+                code = code << 1 | bits(1);
+                len += 1;
+                match (len, code) {
+                    (7, 0..=0x17) => break code + 256,
+                    (8, 0x30..=0xbf) => break code - 0x30,
+                    (8, 0xc0..=0xc7) => break code - 0xc0 + 280,
+                    (9, _) => break code - 0x190 + 144,
+                    _ => {}
+                }
+            };
+            match sym {
+                0..=255 => out.push(sym as u8),
+                256 => return out,
+                _ => {
+                    let l = (sym - 257) as usize;
+                    let len = LENGTH_BASE[l] as usize + bits(LENGTH_EXTRA[l] as u32) as usize;
+                    let d = (0..5).fold(0, |v, _| v << 1 | bits(1)) as usize;
+                    let dist = DIST_BASE[d] as usize + bits(DIST_EXTRA[d] as u32) as usize;
+                    for _ in 0..len {
+                        out.push(out[out.len() - dist]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_huffman() {
+        // Literals only, the same bytes as zlib's raw deflate at level 9
+        assert_eq!(deflate_fixed(b"a"), [0x4b, 0x04, 0x00]);
+        assert_eq!(deflate_fixed(b"ray-rs"), [0x2b, 0x4a, 0xac, 0xd4, 0x2d, 0x2a, 0x06, 0x00]);
+        // Three literals and one match of 9 at distance 3, which zlib inflates back to the input
+        assert_eq!(deflate_fixed(b"abcabcabcabc"), [0x4b, 0x4c, 0x4a, 0x86, 0x23, 0x00]);
+
+        let mut long: Vec<u8> = (0..70000u64).map(|i| (i*i % 251) as u8 ^ (i/1000) as u8).collect();
+        long.extend(std::iter::repeat_n(7u8, 1000));
+        for data in [&b""[..], b"hello, hello, hello world", &[0, 200, 255, 144, 143], &long] {
+            assert_eq!(inflate_fixed(&deflate_fixed(data)), data);
+        }
+    }
+
+    #[test]
+    fn png_layout() {
+        let rgb: Vec<u8> = (0..4*3*3).map(|i| (i*7) as u8).collect();
+        let mut out = Vec::new();
+        write_rgb(&mut out, 4, 3, &rgb).unwrap();
+        assert_eq!(out[..8], SIGNATURE);
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(out[16..24], [0, 0, 0, 4, 0, 0, 0, 3]);
+        assert_eq!(&out[out.len() - 8..out.len() - 4], b"IEND");
+        let ihdr_crc = u32::from_be_bytes(out[29..33].try_into().unwrap());
+        assert_eq!(ihdr_crc, crc32(&out[12..29]));
+    }
+}
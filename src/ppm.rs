@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::{prelude::*, BufReader, Error, ErrorKind};
+use std::path::Path;
+
+use crate::geometry::Vec3f;
+use crate::texture::Image;
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Header fields and sample values of a netpbm file, skipping whitespace and `#` comments.
+struct Tokens<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl Tokens<'_> {
+    fn number(&mut self, what: &str) -> Result<usize, Error> {
+        loop {
+            match self.data.get(self.pos) {
+                Some(b'#') => while self.data.get(self.pos).is_some_and(|&c| c != b'\n') {
+                    self.pos += 1;
+                },
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                _ => break
+            }
+        }
+        let start = self.pos;
+        while self.data.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.data[start..self.pos]).ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid(format!("expected {} at byte {}", what, start)))
+    }
+}
+
+/// Reads a binary (P6) or plain (P3) PPM image, with up to 16 bits per channel.
+pub fn read<R: Read>(r: &mut R) -> Result<Image, Error> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let binary = match data.get(..2) {
+        Some(b"P6") => true,
+        Some(b"P3") => false,
+        _ => return Err(invalid("not a PPM image, expected P3 or P6".to_string()))
+    };
+    let mut t = Tokens { data: &data, pos: 2 };
+    let width = t.number("the width")?;
+    let height = t.number("the height")?;
+    let max = t.number("the maximum value")?;
+    if width == 0 || height == 0 || max == 0 || max > 65535 {
+        return Err(invalid(format!("unsupported PPM size {}x{} with maximum {}", width, height, max)));
+    }
+    let count = width*height*3;
+    let samples: Vec<usize> = if binary {
+        // Exactly one whitespace byte separates the header from the pixels
+        let start = t.pos + 1;
+        let size = if max < 256 { 1 } else { 2 };
+        let body = data.get(start..start + count*size)
+            .ok_or_else(|| invalid("PPM pixel data is truncated".to_string()))?;
+        if size == 1 {
+            body.iter().map(|&b| b as usize).collect()
+        } else {
+            body.chunks(2).map(|c| (c[0] as usize) << 8 | c[1] as usize).collect()
+        }
+    } else {
+        (0..count).map(|_| t.number("a sample")).collect::<Result<_, _>>()?
+    };
+    let scale = 1.0/max as f32;
+    let pixels = samples.chunks(3)
+        .map(|c| Vec3f::new(c[0] as f32, c[1] as f32, c[2] as f32)*scale)
+        .collect();
+    Ok(Image { width, height, pixels })
+}
+
+pub fn load<P: AsRef<Path>>(p: P) -> Result<Image, Error> {
+    let p = p.as_ref();
+    let file = File::open(p).map_err(|e| Error::new(e.kind(), format!("{}: {}", p.display(), e)))?;
+    read(&mut BufReader::new(file)).map_err(|e| Error::new(e.kind(), format!("{}: {}", p.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_and_binary() {
+        let plain = b"P3\n# a comment\n2 1\n255\n255 0 0  0 0 255\n";
+        let image = read(&mut &plain[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, vec![Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 1.0)]);
+
+        let mut binary = b"P6 2 1 255\n".to_vec();
+        binary.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        assert_eq!(read(&mut &binary[..]).unwrap(), image);
+
+        assert!(read(&mut &b"P6 2 1 255\n\x00"[..]).is_err());
+        assert!(read(&mut &b"P5 2 1 255\n"[..]).is_err());
+    }
+}
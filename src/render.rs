@@ -2,12 +2,19 @@ use std::fs::{File};
 use std::io::{prelude::*, Error, BufWriter};
 use std::mem::swap;
 use std::path::Path;
+use std::str::FromStr;
 
 use rayon::prelude::*;
 
+use crate::bvh::{Aabb, Bvh};
+use crate::camera::Camera;
 use crate::geometry::Vec3f;
-use crate::march::{ray_march};
-use crate::sphere::{Sphere, Material};
+use crate::march::{ray_march, Kaboom, SdfScene};
+use crate::png;
+use crate::sampling::{cosine_hemisphere, Filter, Pattern, Rng};
+use crate::hittable::{Hit, Hittable};
+pub use crate::light::Light;
+use crate::material::{alpha, f0, sample_ggx, schlick, smith, transmittance, Model};
 
 pub struct Frame {
     framebuffer:Vec<Vec3f>, 
@@ -18,164 +25,470 @@ pub struct Frame {
 pub struct View {
     width: usize,
     height:usize,
-    fov: f32
+    camera: Camera,
+    depth: usize,
+    samples: usize,
+    shadow_samples: usize,
+    pattern: Pattern,
+    filter: Filter
 }
 
 pub enum RenderType<'a> {
-    Kaboom,
-    RayTrace(&'a Scene<'a>)
+    Kaboom(Kaboom),
+    /// A distance field marched from the camera.
+    March(&'a SdfScene),
+    RayTrace(&'a Scene<'a>),
+    /// Monte Carlo path tracing, `View::depth` bounces before Russian roulette starts.
+    PathTrace(&'a Scene<'a>)
 }
 
+// Rows rendered by one task; samples near a band edge are splatted into a
+// margin that overlaps the neighbouring bands and is summed afterwards
+const BAND: usize = 16;
+
 impl View {
     pub fn new(width:usize, height: usize, fov: f32) -> Self {
-        Self { width, height, fov }
+        Self::from_camera(width, height, Camera::perspective(fov))
+    }
+
+    /// A view through `camera`, with the same defaults as `View::new`.
+    pub fn from_camera(width: usize, height: usize, camera: Camera) -> Self {
+        Self {
+            width,
+            height,
+            camera,
+            depth: 4,
+            samples: 1,
+            shadow_samples: 1,
+            pattern: Pattern::Regular,
+            filter: Filter::Box
+        }
+    }
+
+    pub fn with_camera(self, camera: Camera) -> Self {
+        Self { camera, ..self }
+    }
+
+    /// Recursion limit for reflected and refracted rays.
+    pub fn with_depth(self, depth: usize) -> Self {
+        Self { depth, ..self }
+    }
+
+    /// Rays per pixel, placed according to the sample pattern.
+    pub fn with_samples(self, samples: usize) -> Self {
+        Self { samples: samples.max(1), ..self }
+    }
+
+    /// Shadow rays per area light at every shading point.
+    pub fn with_shadow_samples(self, shadow_samples: usize) -> Self {
+        Self { shadow_samples: shadow_samples.max(1), ..self }
+    }
+
+    pub fn with_pattern(self, pattern: Pattern) -> Self {
+        Self { pattern, ..self }
+    }
+
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Self { filter, ..self }
+    }
+
+    /// Color seen through the image point `(px, py)`, in pixels from the top left corner.
+    fn sample(&self, scene: &RenderType, px: f32, py: f32, rng: &mut Rng) -> Vec3f {
+        let half = self.height as f32 / 2.0;
+        let x = (px - self.width as f32 / 2.0)/half;
+        let y = (self.height as f32 / 2.0 - py)/half;
+        let (orig, dir) = self.camera.ray(x, y);
+        let pixel = match scene {
+            RenderType::Kaboom(k) => ray_march(k, orig, dir, rng),
+            RenderType::March(s) => s.cast_ray(orig, dir, rng),
+            RenderType::RayTrace(s) => s.cast_ray(orig, dir, self.depth, None, self.shadow_samples, rng),
+            RenderType::PathTrace(s) => s.path_trace(orig, dir, self.depth, self.shadow_samples, rng)
+        };
+        let max = pixel[0].max(pixel[1].max(pixel[2]));
+        if max > 1.0 {
+            pixel * (1.0/max)
+        } else {
+            pixel
+        }
     }
 
     pub fn render(&self, scene: RenderType) -> Frame {
-        let fheight = self.height as f32;
-        let fwidth = self.width as f32;
-        let mut framebuffer: Vec<Vec3f> = Vec::with_capacity(self.width*self.height);
-        framebuffer.resize(self.width*self.height, Vec3f::zero());
-        let orig = Vec3f::zero();
-        let z = -fheight/(2.0*f32::tan(self.fov/2.0));
-        framebuffer.par_chunks_mut(self.width).enumerate()
-        .for_each(|(j, row)| {
-            let fj = j as f32;
-            let y = -(fj + 0.5) + fheight / 2.0;
-            for (i,p) in row.iter_mut().enumerate() {
-                let fi = i as f32;
-                let x =  (fi + 0.5) - fwidth / 2.0;
-                let dir = Vec3f::new(x, y, z).normalize();
-                let pixel = match scene {
-                    RenderType::Kaboom => ray_march(dir),
-                    RenderType::RayTrace(s) => s.cast_ray(orig, dir,  4)
-                };
-                let max = pixel[0].max(pixel[1].max(pixel[2]));
-                *p =  if max > 1.0 {
-                    pixel * (1.0/max)
-                } else {
-                    pixel
-                };
+        let (width, height) = (self.width, self.height);
+        let r = self.filter.radius();
+        let margin = (r + 0.5).ceil() as usize;
+        let bands: Vec<(usize, Vec<Splat>)> = (0..height.div_ceil(BAND)).into_par_iter()
+        .map(|b| {
+            let (start, end) = (b*BAND, ((b + 1)*BAND).min(height));
+            let (lo, hi) = (start.saturating_sub(margin), (end + margin).min(height));
+            let mut acc = vec![Splat::default(); (hi - lo)*width];
+            let mut offsets = Vec::with_capacity(self.samples);
+            for j in start..end {
+                for i in 0..width {
+                    let mut rng = Rng::new((j*width + i) as u64);
+                    self.pattern.offsets(self.samples, &mut rng, &mut offsets);
+                    for &(dx, dy) in &offsets {
+                        let (px, py) = (i as f32 + dx, j as f32 + dy);
+                        let color = self.sample(&scene, px, py, &mut rng);
+                        let own = &mut acc[(j - lo)*width + i];
+                        own.sum = own.sum + color;
+                        own.count += 1;
+                        // Every pixel whose center lies within the filter radius gets a share
+                        let x0 = (px - 0.5 - r).ceil().max(0.0) as usize;
+                        let x1 = ((px - 0.5 + r).floor() as usize).min(width - 1);
+                        let y0 = ((py - 0.5 - r).ceil().max(0.0) as usize).max(lo);
+                        let y1 = ((py - 0.5 + r).floor() as usize).min(hi - 1);
+                        for y in y0..=y1 {
+                            for x in x0..=x1 {
+                                let w = self.filter.weight(px - x as f32 - 0.5, py - y as f32 - 0.5);
+                                if w != 0.0 {
+                                    let a = &mut acc[(y - lo)*width + x];
+                                    a.weighted = a.weighted + color*w;
+                                    a.weight += w;
+                                }
+                            }
+                        }
+                    }
+                }
             }
-        });
-        Frame { framebuffer, width: self.width, height: self.height }
+            (lo, acc)
+        }).collect();
+
+        let mut acc = vec![Splat::default(); width*height];
+        for (lo, band) in bands {
+            for (a, b) in acc[lo*width..].iter_mut().zip(band) {
+                a.add(&b);
+            }
+        }
+        let framebuffer = acc.iter().map(Splat::color).collect();
+        Frame { framebuffer, width, height }
+    }
+}
+
+/// Filtered samples landing on one pixel, plus the plain sum of the pixel's own samples
+#[derive(Clone, Copy)]
+struct Splat {
+    weighted: Vec3f,
+    weight: f32,
+    sum: Vec3f,
+    count: usize
+}
+
+impl Default for Splat {
+    fn default() -> Self {
+        Self { weighted: Vec3f::zero(), weight: 0.0, sum: Vec3f::zero(), count: 0 }
+    }
+}
+
+impl Splat {
+    fn add(&mut self, other: &Splat) {
+        self.weighted = self.weighted + other.weighted;
+        self.weight += other.weight;
+        self.sum = self.sum + other.sum;
+        self.count += other.count;
+    }
+
+    /// The filtered color, or the unweighted mean where the filter weights cancel out.
+    fn color(&self) -> Vec3f {
+        if self.weight > 0.0 {
+            self.weighted*(1.0/self.weight)
+        } else if self.count > 0 {
+            self.sum*(1.0/self.count as f32)
+        } else {
+            Vec3f::zero()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png
+}
+
+impl ImageFormat {
+    /// PNG for a `.png` extension, PPM for anything else.
+    pub fn from_path(p: &str) -> Self {
+        match Path::new(p).extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("png") => ImageFormat::Png,
+            _ => ImageFormat::Ppm
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ppm" => Ok(ImageFormat::Ppm),
+            "png" => Ok(ImageFormat::Png),
+            _ => Err(format!("unknown format '{}': expected ppm or png", s))
+        }
     }
 }
 
 impl Frame {
+    /// Saves in the format implied by the file extension.
     pub fn save(&self, p: &str) -> Result<(), Error> {
+        self.save_as(p, ImageFormat::from_path(p))
+    }
+
+    pub fn save_as(&self, p: &str, format: ImageFormat) -> Result<(), Error> {
         let path = Path::new(p);
-        let mut file = File::create(&path)?;
-        write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
-        let mut file_buff = BufWriter::new(file);
-        for point in &self.framebuffer {
-            for i in 0..3 {
-                file_buff.write_all(
-                    &[(255.0f32 * 
-                        f32::max(0.0, f32::min(1.0, point[i]))) as u8])?;
-                    }
-                }
-                Ok(())
-            }
+        let mut file = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Ppm => {
+                write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+                file.write_all(&self.to_rgb8())?;
+            },
+            ImageFormat::Png => png::write_rgb(&mut file, self.width, self.height, &self.to_rgb8())?
+        }
+        file.flush()
+    }
+
+    /// Pixels clamped to 0..1 and quantized to 8 bits, rows top to bottom.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.framebuffer.iter()
+            .flat_map(|point| (0..3).map(move |i| (255.0f32 * point[i].clamp(0.0, 1.0)) as u8))
+            .collect()
+    }
 }
 
+const MAX_DIST: f32 = 10000.0;
+
 pub struct Scene<'a> {
-    spheres: Vec<Sphere<'a>>,
-    lights: Vec<Light>
+    objects: Vec<Box<dyn Hittable + 'a>>,
+    lights: Vec<Light>,
+    bvh: Bvh,
+    // BVH leaves index into `bounded`; objects without finite bounds, like planes, are tested one by one
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>
 }
 
 impl<'a> Scene<'a> {
-    pub fn new(spheres: Vec<Sphere<'a>>, lights: Vec<Light>) -> Self {
-        Self { spheres, lights }
-    }
-
-    fn scene_intersect (&self, orig: Vec3f, dir: Vec3f) -> Option<(Vec3f, Vec3f, Material)> {
-        let mut spheres_dist = f32::MAX;
-        let mut hit = Vec3f::zero();
-        let mut n = Vec3f::zero();
-        let mut material = Material::default();
-        for i in &self.spheres {
-            if let Some(dist_i) = i.ray_intersect(orig, dir) {
-                if dist_i < spheres_dist {
-                    spheres_dist = dist_i;
-                    hit = orig + dir*dist_i;
-                    n = (hit - i.center).normalize();
-                    material = *i.material;
-                }
-            }
-        }
-    
-        let mut board_dist = f32::MAX;
-        if dir[1].abs() > 1e-3 {
-            let d = -(orig[1] + 4.0)/dir[1];
-            let pt = orig + dir*d;
-            if d>0.0 && pt[0].abs()<10.0 && pt[2]< -10.0 && pt[2]> -30.0 && d<spheres_dist {
-                board_dist = d;
-                hit = pt;
-                n = Vec3f::from([0.0, 1.0, 0.0]);
-                material.diffuse_color = if ((0.5*hit[0]+1000.0) as i32 + (0.5*hit[2]) as i32) & 1 == 1 {
-                    Vec3f::new(0.9, 0.9, 0.9)
-                } else {
-                    Vec3f::new(0.92, 0.69, 0.078)
-                }*0.3;
+    pub fn new(objects: Vec<Box<dyn Hittable + 'a>>, lights: Vec<Light>) -> Self {
+        let (bounded, unbounded): (Vec<usize>, Vec<usize>) = (0..objects.len())
+            .partition(|&i| objects[i].bounds().surface_area().is_finite());
+        let bounds: Vec<Aabb> = bounded.iter().map(|&i| objects[i].bounds()).collect();
+        let bvh = Bvh::new(&bounds);
+        Self { objects, lights, bvh, bounded, unbounded }
+    }
+
+    fn scene_intersect (&self, orig: Vec3f, dir: Vec3f) -> Option<Hit<'_>> {
+        let mut closest = self.bvh.closest(orig, dir, MAX_DIST, |i, t| {
+            self.objects[self.bounded[i]].hit(orig, dir, t).map(|h| (h.dist, h))
+        });
+        for &i in &self.unbounded {
+            let t = closest.as_ref().map_or(MAX_DIST, |h: &Hit| h.dist);
+            if let Some(h) = self.objects[i].hit(orig, dir, t) {
+                closest = Some(h);
             }
         }
-    
-        let dist = f32::min(spheres_dist, board_dist);
-        if dist < 10000.0 {
-            Some((hit,n,material))
-        } else {
-            None
-        }
+        closest
+    }
+
+    fn occluded(&self, orig: Vec3f, dir: Vec3f, dist: f32) -> bool {
+        self.unbounded.iter().any(|&i| self.objects[i].hit(orig, dir, dist).is_some())
+        || self.bvh.any(orig, dir, dist, |i, t| {
+            self.objects[self.bounded[i]].hit(orig, dir, t).is_some()
+        })
     }
 
-    fn cast_ray(&self, orig: Vec3f, dir: Vec3f, depth: usize) -> Vec3f {
+    /// Whitted-style color seen along `dir`. `medium` is the absorption of the
+    /// material the ray travels through, `None` outside of all objects.
+    fn cast_ray(&self, orig: Vec3f, dir: Vec3f, depth: usize, medium: Option<Vec3f>, shadows: usize, rng: &mut Rng) -> Vec3f {
         if depth == 0 {
-            return Vec3f::new(0.2, 0.7, 0.8)
+            return absorb(medium, Vec3f::new(0.2, 0.7, 0.8), f32::INFINITY)
         }
-        let (hit, n, material) = match self.scene_intersect(orig, dir) {
-            Some((x,y,z)) => (x,y,z),
-            None => return Vec3f::new(0.2, 0.7, 0.8)
+        let h = match self.scene_intersect(orig, dir) {
+            Some(h) => h,
+            None => return absorb(medium, Vec3f::new(0.2, 0.7, 0.8), f32::INFINITY)
         };
+        let (hit, n, material) = (h.point, h.normal, h.material);
+        let base_color = material.diffuse_color.eval(h.uv, hit);
     
-        let reflect_dir = reflect(dir, n).normalize();
+        let reflect_dir = dir.reflect(n).normalize();
         let reflect_orig = normal_offset(hit, n, reflect_dir);
-        let reflect_color = self.cast_ray(reflect_orig, reflect_dir, depth-1);
+        let reflect_color = self.cast_ray(reflect_orig, reflect_dir, depth-1, medium, shadows, rng);
     
-        let refract_dir = refract(dir, n, material.refractive_index).normalize();
-        let refract_orig = normal_offset(hit, n, refract_dir);
-        let refract_color = self.cast_ray(refract_orig, refract_dir, depth-1);
+        // Whatever glass does not transmit it reflects, everything under total internal reflection
+        let kr = fresnel(dir, n, material.refractive_index);
+        let refract_color = match refract(dir, n, material.refractive_index) {
+            Some(refract_dir) if kr < 1.0 => {
+                let refract_dir = refract_dir.normalize();
+                let refract_orig = normal_offset(hit, n, refract_dir);
+                self.cast_ray(refract_orig, refract_dir, depth-1, entered(dir, &h), shadows, rng)
+            },
+            _ => Vec3f::zero()
+        };
     
-        let mut diffuse_light_intencity = 0.0;
-        let mut specular_light_intensity = 0.0;
+        let direct = self.direct_light(&h, dir, base_color, shadows, rng);
+        let color = match material.model {
+            Model::Phong => direct
+                + reflect_color*(material.albedo[2] + material.albedo[3]*kr)
+                + refract_color*(material.albedo[3]*(1.0 - kr)),
+            Model::MetallicRoughness { metallic, roughness } => {
+                // Only a mirror-like sharp reflection can be traced here, it fades out as the surface gets rougher
+                let smooth = 1.0 - roughness.clamp(0.0, 1.0);
+                let fresnel = schlick(f0(base_color, metallic), (dir*n).abs());
+                direct + reflect_color.hadamard(fresnel)*(smooth*smooth)
+            }
+        };
+        absorb(medium, color, h.dist)
+    }
+
+    /// Light of all unshadowed lights reflected at `h`, where the diffuse texture evaluates to `base_color`.
+    /// Area lights are averaged over `shadows` random points, which gives soft
+    /// penumbrae that get smoother as the count grows.
+    fn direct_light(&self, h: &Hit, dir: Vec3f, base_color: Vec3f, shadows: usize, rng: &mut Rng) -> Vec3f {
+        let (hit, n) = (h.point, h.normal);
+        let mut color = Vec3f::zero();
         for light in &self.lights {
-            let light_dir = (light.pos - hit).normalize();
-            let light_dist = (light.pos - hit).norm();
-            let shadow_orig = normal_offset(hit, n, light_dir); 
-            if let Some((shadow_pt,_,_)) = self.scene_intersect(shadow_orig, light_dir) {
-                if (shadow_pt-shadow_orig).norm() < light_dist {
+            let count = light.shadow_samples(shadows);
+            let weight = 1.0/count as f32;
+            for _ in 0..count {
+                let incident = light.incident(hit, rng);
+                if incident.radiance == Vec3f::zero() {
+                    continue;
+                }
+                let light_dir = incident.dir;
+                let shadow_orig = normal_offset(hit, n, light_dir);
+                if self.occluded(shadow_orig, light_dir, incident.dist) {
                     continue;
                 }
+                color = color + h.material.shade(base_color, n, dir, light_dir).hadamard(incident.radiance)*weight;
+            }
+        }
+        color
+    }
+
+    /// One path sample of the light arriving along `dir`. Lights are sampled
+    /// directly at every vertex with the same terms as `cast_ray`, the path then
+    /// continues through one of the diffuse, mirror or refraction lobes, picked
+    /// in proportion to its albedo, or through a sampled GGX microfacet for
+    /// metallic/roughness materials. After `min_depth` bounces paths are ended
+    /// by Russian roulette, which adds no bias by itself; paths are still cut at
+    /// `MAX_BOUNCES`, a guard for albedos that never let the roulette end them.
+    pub fn path_trace(&self, orig: Vec3f, dir: Vec3f, min_depth: usize, shadows: usize, rng: &mut Rng) -> Vec3f {
+        let mut color = Vec3f::zero();
+        let mut throughput = Vec3f::one();
+        let (mut orig, mut dir) = (orig, dir);
+        let mut medium = None;
+        for bounce in 0..MAX_BOUNCES {
+            let h = match self.scene_intersect(orig, dir) {
+                Some(h) => h,
+                None => return color + throughput.hadamard(absorb(medium, Vec3f::new(0.2, 0.7, 0.8), f32::INFINITY))
+            };
+            throughput = absorb(medium, throughput, h.dist);
+            let (hit, n, material) = (h.point, h.normal, h.material);
+            let base_color = material.diffuse_color.eval(h.uv, hit);
+            color = color + throughput.hadamard(self.direct_light(&h, dir, base_color, shadows, rng));
+
+            let facing = if dir*n > 0.0 { -n } else { n };
+            let next = match material.model {
+                Model::Phong => {
+                    let weights = [
+                        (material.albedo[0]*luminance(base_color)).max(0.0),
+                        material.albedo[2].max(0.0),
+                        material.albedo[3].max(0.0)
+                    ];
+                    let total: f32 = weights.iter().sum();
+                    if total <= 0.0 {
+                        break;
+                    }
+                    let mut u = rng.next_f32()*total;
+                    let lobe = weights.iter().position(|&w| { u -= w; u < 0.0 }).unwrap_or(2);
+                    match lobe {
+                        0 => {
+                            throughput = throughput.hadamard(base_color)*(material.albedo[0]*total/weights[0]);
+                            cosine_hemisphere(facing, rng)
+                        },
+                        1 => {
+                            throughput = throughput*(material.albedo[2]*total/weights[1]);
+                            dir.reflect(n).normalize()
+                        },
+                        _ => {
+                            throughput = throughput*(material.albedo[3]*total/weights[2]);
+                            let kr = fresnel(dir, n, material.refractive_index);
+                            match refract(dir, n, material.refractive_index) {
+                                Some(t) if rng.next_f32() >= kr => {
+                                    medium = entered(dir, &h);
+                                    t
+                                },
+                                _ => dir.reflect(n).normalize()
+                            }
+                        }
+                    }
+                },
+                Model::MetallicRoughness { metallic, roughness } => {
+                    let v = -dir;
+                    let nv = (v*facing).max(1e-4);
+                    let alpha = alpha(roughness);
+                    let f0 = f0(base_color, metallic);
+                    let p_specular = luminance(schlick(f0, nv)).clamp(0.1, 0.9);
+                    if rng.next_f32() < p_specular {
+                        let m = sample_ggx(facing, alpha, rng);
+                        let l = dir.reflect(m);
+                        let (nl, nh, vh) = (l*facing, m*facing, v*m);
+                        if nl <= 0.0 || vh <= 0.0 {
+                            break;
+                        }
+                        // GGX cancels against the half vector pdf
+                        throughput = throughput.hadamard(schlick(f0, vh))*(smith(nv, nl, alpha)*vh/(nv*nh*p_specular));
+                        l
+                    } else {
+                        let l = cosine_hemisphere(facing, rng);
+                        let f = schlick(f0, v*(v + l).normalize());
+                        throughput = throughput.hadamard((Vec3f::one() - f).hadamard(base_color))
+                            *((1.0 - metallic)/(1.0 - p_specular));
+                        l
+                    }
+                }
+            };
+            let next = next.normalize();
+            orig = normal_offset(hit, n, next);
+            dir = next;
+
+            if bounce + 1 >= min_depth {
+                let p = throughput[0].max(throughput[1].max(throughput[2])).min(1.0);
+                if rng.next_f32() >= p {
+                    break;
+                }
+                throughput = throughput*(1.0/p);
             }
-            diffuse_light_intencity += light.intensity * f32::max(0.0, light_dir*n);
-            let rf = reflect(light_dir, n)*dir;
-            specular_light_intensity += rf.max(0.0).powf(material.specular_exp)*light.intensity;
         }
-        material.diffuse_color * diffuse_light_intencity
-        * material.albedo[0] + Vec3f::one()*specular_light_intensity * material.albedo[1]
-        + reflect_color*material.albedo[2]
-        + refract_color*material.albedo[3]
+        color
+    }
+}
+
+/// Beer-Lambert attenuation of `color` after travelling `dist` through a medium with absorption `medium`.
+fn absorb(medium: Option<Vec3f>, color: Vec3f, dist: f32) -> Vec3f {
+    match medium {
+        Some(a) => color.hadamard(transmittance(a, dist)),
+        None => color
     }
 }
 
+/// Absorption on the far side of the surface at `h` for a ray refracted through it,
+/// sampled where the ray enters. Objects are assumed not to overlap, so leaving
+/// one means being back in air.
+fn entered(dir: Vec3f, h: &Hit) -> Option<Vec3f> {
+    if dir*h.normal < 0.0 { Some(h.material.absorption.eval(h.uv, h.point)) } else { None }
+}
 
+// Safety net for materials whose albedos add up to more than one, which would
+// otherwise keep Russian roulette from ever ending the path
+const MAX_BOUNCES: usize = 64;
 
-fn reflect(i: Vec3f, n: Vec3f) -> Vec3f {
-    i - n*2.0f32*(i*n)
+fn luminance(c: Vec3f) -> f32 {
+    0.2126*c[0] + 0.7152*c[1] + 0.0722*c[2]
 }
 
-fn refract(i: Vec3f, n: Vec3f, rf_index: f32) -> Vec3f {
-    let mut cosi = -f32::max(-1.0, f32::min(1.0, i*n));
+
+
+/// Refracted direction, `None` under total internal reflection.
+fn refract(i: Vec3f, n: Vec3f, rf_index: f32) -> Option<Vec3f> {
+    let mut cosi = -(i*n).clamp(-1.0, 1.0);
     let mut etai = 1.0;
     let mut etat = rf_index;
     let n_i = if cosi < 0.0 {
@@ -188,24 +501,192 @@ fn refract(i: Vec3f, n: Vec3f, rf_index: f32) -> Vec3f {
     let eta  = etai / etat;
     let k = 1.0 - eta*eta*(1.0 - cosi*cosi);
     if k<0.0 {
-        Vec3f::zero()
+        None
     } else {
-        i*eta + n_i*(eta * cosi - k.sqrt())
+        Some(i*eta + n_i*(eta * cosi - k.sqrt()))
     }
 }
 
+/// Fraction of unpolarized light reflected by a dielectric boundary, from the
+/// Fresnel equations. Rays leaving the medium may be totally reflected.
+fn fresnel(i: Vec3f, n: Vec3f, rf_index: f32) -> f32 {
+    let mut cosi = (i*n).clamp(-1.0, 1.0);
+    let (mut etai, mut etat) = (1.0, rf_index);
+    if cosi > 0.0 {
+        swap(&mut etai, &mut etat);
+    }
+    let sint = etai/etat*(1.0 - cosi*cosi).max(0.0).sqrt();
+    if sint >= 1.0 {
+        return 1.0;
+    }
+    let cost = (1.0 - sint*sint).max(0.0).sqrt();
+    cosi = cosi.abs();
+    let rs = (etat*cosi - etai*cost)/(etat*cosi + etai*cost);
+    let rp = (etai*cosi - etat*cost)/(etai*cosi + etat*cost);
+    (rs*rs + rp*rp)/2.0
+}
+
 #[inline]
 fn normal_offset(v: Vec3f, n: Vec3f, dir: Vec3f) -> Vec3f {
     v + n*1e-3*(dir*n).signum()
 }
 
-pub struct Light {
-    pos: Vec3f,
-    intensity: f32
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use crate::geometry::Vec4f;
+    use crate::material::Material;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let materials = [
+            Material { diffuse_color: Vec3f::new(0.4, 0.4, 0.3).into(), albedo: Vec4f::new(0.6, 0.3, 0.1, 0.0), specular_exp: 50., refractive_index: 1.0, ..Material::default() },
+            Material { diffuse_color: Vec3f::new(0.6, 0.7, 0.8).into(), albedo: Vec4f::new(0.0, 0.5, 0.1, 0.8), specular_exp: 125., refractive_index: 2.5, ..Material::default() },
+            Material { diffuse_color: Vec3f::one().into(), albedo: Vec4f::new(0.2, 10.0, 0.8, 0.0), specular_exp: 1425., refractive_index: 1.0, ..Material::default() }
+        ];
+        let mut seed = 7u32;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1u32 << 24) as f32
+        };
+        let spheres: Vec<Box<dyn Hittable>> = (0..300).map(|i| Box::new(Sphere::new(
+            Vec3f::new(rand()*20.0 - 10.0, rand()*12.0 - 4.0, -10.0 - rand()*25.0),
+            0.2 + rand()*0.6,
+            &materials[i % materials.len()]
+        )) as Box<dyn Hittable>).collect();
+        let lights = vec![
+            Light::new(Vec3f::new(-20.0, 20.0,  20.0), 1.5),
+            Light::new(Vec3f::new( 30.0, 50.0, -25.0), 1.8)
+        ];
+        let view = View::new(64, 48, PI / 3.0);
+        let mut scene = Scene::new(spheres, lights);
+        let fast = view.render(RenderType::RayTrace(&scene));
+
+        let bounds: Vec<Aabb> = scene.bounded.iter().map(|&i| scene.objects[i].bounds()).collect();
+        scene.bvh = Bvh::with_leaf_size(&bounds, usize::MAX);
+        let brute = view.render(RenderType::RayTrace(&scene));
+
+        for (a, b) in fast.framebuffer.iter().zip(&brute.framebuffer) {
+            for i in 0..3 {
+                assert_eq!(a[i], b[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn area_light_penumbra() {
+        use crate::plane::Plane;
+        // A sphere light above a ball floating over the floor
+        let matte = Material { diffuse_color: Vec3f::one().into(), ..Material::default() };
+        let floor = || Box::new(Plane::new(Vec3f::zero(), Vec3f::new(0.0, 1.0, 0.0), &matte)) as Box<dyn Hittable>;
+        let ball = Box::new(Sphere::new(Vec3f::new(0.0, 2.0, 0.0), 1.0, &matte)) as Box<dyn Hittable>;
+        let light = Light::sphere(Vec3f::new(0.0, 6.0, 0.0), 1.5, 1.0);
+        let shadowed = Scene::new(vec![floor(), ball], vec![light]);
+        let open = Scene::new(vec![floor()], vec![light]);
+        let down = Vec3f::new(0.0, -1.0, 0.0);
+        let floor_at = |scene: &Scene, x: f32, shadows: usize, seed: u64|
+            scene.cast_ray(Vec3f::new(x, 0.5, 0.0), down, 1, None, shadows, &mut Rng::new(seed))[0];
+
+        // Umbra under the ball, a partly covered light beside it and full light further out
+        assert_eq!(floor_at(&shadowed, 0.0, 64, 1), 0.0);
+        assert_eq!(floor_at(&shadowed, 4.0, 64, 1), floor_at(&open, 4.0, 64, 1));
+        let penumbra = floor_at(&shadowed, 1.0, 1024, 1)/floor_at(&open, 1.0, 1024, 1);
+        assert!(penumbra > 0.1 && penumbra < 0.9, "{}", penumbra);
 
-impl Light {
-    pub fn new(pos: Vec3f, intensity: f32) -> Self {
-        Self {pos, intensity}
+        // More shadow rays give a less noisy penumbra
+        let spread = |shadows: usize| {
+            let v: Vec<f32> = (0..200).map(|seed| floor_at(&shadowed, 1.0, shadows, seed)).collect();
+            let mean = v.iter().sum::<f32>()/v.len() as f32;
+            (v.iter().map(|x| (x - mean)*(x - mean)).sum::<f32>()/v.len() as f32).sqrt()
+        };
+        let (few, many) = (spread(4), spread(64));
+        assert!(many < few*0.5, "{} {}", few, many);
+    }
+
+    #[test]
+    fn sdf_objects_shadow_and_reflect() {
+        use crate::plane::Plane;
+        use crate::sdf::{self, Sdf, SdfObject};
+        // A half mirror ball over the floor, once analytic and once marched
+        let matte = Material { diffuse_color: Vec3f::one().into(), ..Material::default() };
+        let shiny = Material { diffuse_color: Vec3f::one().into(), albedo: Vec4f::new(0.5, 0.0, 0.5, 0.0), specular_exp: 1.0, ..Material::default() };
+        let center = Vec3f::new(0.0, 2.0, 0.0);
+        let scene = |marched: bool, floor: bool| {
+            let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+            if floor {
+                objects.push(Box::new(Plane::new(Vec3f::zero(), Vec3f::new(0.0, 1.0, 0.0), &matte)));
+            }
+            objects.push(if marched {
+                Box::new(SdfObject::new(sdf::Sphere::new(1.0).translate(center), Aabb::new(center - Vec3f::one(), center + Vec3f::one()), &shiny))
+            } else {
+                Box::new(Sphere::new(center, 1.0, &shiny))
+            });
+            Scene::new(objects, vec![Light::new(Vec3f::new(1.0, 6.0, 2.0), 1.0)])
+        };
+        let cast = |scene: &Scene, orig: Vec3f, dir: Vec3f| scene.cast_ray(orig, dir.normalize(), 4, None, 1, &mut Rng::new(1));
+        let (analytic, marched) = (scene(false, true), scene(true, true));
+
+        // The floor under the ball is in its shadow either way
+        let down = Vec3f::new(0.0, -1.0, 0.0);
+        let umbra = Vec3f::new(-0.5, 0.5, -1.0);
+        assert_eq!(cast(&marched, umbra, down), Vec3f::zero());
+        assert_eq!(cast(&analytic, umbra, down), Vec3f::zero());
+        assert!(cast(&marched, Vec3f::new(4.0, 0.5, 0.0), down)[0] > 0.0);
+
+        // The lower half of the ball shows the floor, as the analytic ball does
+        let (orig, dir) = (Vec3f::new(0.2, 1.4, 5.0), Vec3f::new(0.0, 0.0, -1.0));
+        let (a, b) = (cast(&analytic, orig, dir), cast(&marched, orig, dir));
+        assert!((a - b).norm() < 1e-2, "{:?} {:?}", a, b);
+        let without_floor = cast(&scene(true, false), orig, dir);
+        assert!((b - without_floor).norm() > 0.05, "{:?} {:?}", b, without_floor);
+    }
+
+    #[test]
+    fn path_trace_follows_mirrors() {
+        // With only mirror lobes the path tracer is deterministic and must agree with the Whitted tracer
+        let mirror = Material { diffuse_color: Vec3f::one().into(), albedo: Vec4f::new(0.0, 0.0, 1.0, 0.0), specular_exp: 1.0, refractive_index: 1.0, ..Material::default() };
+        let spheres: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Sphere::new(Vec3f::new(-1.0, 0.0, -8.0), 1.5, &mirror)),
+            Box::new(Sphere::new(Vec3f::new(2.0, 1.0, -10.0), 2.0, &mirror))
+        ];
+        let scene = Scene::new(spheres, Vec::new());
+        let view = View::new(32, 24, PI / 3.0).with_depth(8);
+        let whitted = view.render(RenderType::RayTrace(&scene));
+        let traced = view.render(RenderType::PathTrace(&scene));
+        for (a, b) in whitted.framebuffer.iter().zip(&traced.framebuffer) {
+            assert!((*a - *b).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn absorbing_sphere_tints() {
+        // Clear glass that does not bend light, so the ray crosses 2 units of the medium head on
+        let tinted = Material {
+            albedo: Vec4f::new(0.0, 0.0, 0.0, 1.0),
+            absorption: Vec3f::new(0.0, 1.0, 2.0).into(),
+            ..Material::default()
+        };
+        let scene = Scene::new(vec![Box::new(Sphere::new(Vec3f::zero(), 1.0, &tinted))], Vec::new());
+        let (orig, dir) = (Vec3f::new(0.0, 0.0, 5.0), Vec3f::new(0.0, 0.0, -1.0));
+        let expected = Vec3f::new(0.2, 0.7, 0.8).hadamard(transmittance(Vec3f::new(0.0, 1.0, 2.0), 2.0));
+        let whitted = scene.cast_ray(orig, dir, 4, None, 1, &mut Rng::new(1));
+        let traced = scene.path_trace(orig, dir, 8, 1, &mut Rng::new(1));
+        assert!((whitted - expected).norm() < 1e-3, "{:?}", whitted);
+        assert!((traced - expected).norm() < 1e-3, "{:?}", traced);
+    }
+
+    #[test]
+    fn fresnel_reflectance() {
+        let n = Vec3f::new(0.0, 0.0, 1.0);
+        let head_on = Vec3f::new(0.0, 0.0, -1.0);
+        assert!((fresnel(head_on, n, 1.5) - 0.04).abs() < 1e-6);
+        let grazing = Vec3f::new(1.0, 0.0, -0.05).normalize();
+        assert!(fresnel(grazing, n, 1.5) > 0.7);
+        // Leaving glass at 60 degrees is past the critical angle of about 42
+        let leaving = Vec3f::new(0.866, 0.0, 0.5);
+        assert_eq!(fresnel(leaving, n, 1.5), 1.0);
+        assert!(refract(leaving, n, 1.5).is_none());
     }
-}
\ No newline at end of file
+}
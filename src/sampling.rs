@@ -0,0 +1,272 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::geometry::Vec3f;
+
+/// Small, fast xorshift generator. Renders seed one per pixel, so images are
+/// reproducible no matter how rows are split between threads.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 finalizer, spreads neighbouring seeds over the whole state
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+/// Where inside a pixel the samples are placed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// Evenly spaced grid, a single sample hits the pixel center.
+    Regular,
+    /// One random point inside every cell of the grid.
+    Jittered,
+    /// Halton sequence in bases 2 and 3, randomly shifted per pixel.
+    Halton,
+    /// Sobol (0,2)-sequence, randomly scrambled per pixel.
+    Sobol
+}
+
+fn radical_inverse(base: u32, mut i: u32) -> f32 {
+    let inv = 1.0/base as f32;
+    let mut f = inv;
+    let mut r = 0.0;
+    while i > 0 {
+        r += f*(i % base) as f32;
+        i /= base;
+        f *= inv;
+    }
+    r
+}
+
+fn sobol2(mut i: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut r = 0;
+    while i > 0 {
+        if i & 1 == 1 {
+            r ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    r
+}
+
+fn unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regular" => Ok(Pattern::Regular),
+            "jittered" => Ok(Pattern::Jittered),
+            "halton" => Ok(Pattern::Halton),
+            "sobol" => Ok(Pattern::Sobol),
+            _ => Err(format!("unknown sample pattern '{}': expected regular, jittered, halton or sobol", s))
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Pattern::Regular => "regular",
+            Pattern::Jittered => "jittered",
+            Pattern::Halton => "halton",
+            Pattern::Sobol => "sobol"
+        })
+    }
+}
+
+/// Splits `n` samples into an `nx` by `ny` grid with `nx*ny == n`, as close to square as `n` allows.
+fn grid(n: usize) -> (usize, usize) {
+    let ny = (1..=(n as f32).sqrt() as usize).rev().find(|d| n.is_multiple_of(*d)).unwrap_or(1);
+    (n / ny, ny)
+}
+
+impl Pattern {
+    /// Replaces the contents of `out` with the offsets of `n` samples within one
+    /// pixel, both coordinates in [0, 1). `rng` should be seeded per pixel.
+    pub fn offsets(&self, n: usize, rng: &mut Rng, out: &mut Vec<(f32, f32)>) {
+        out.clear();
+        let (nx, ny) = grid(n);
+        match self {
+            Pattern::Regular => out.extend((0..n).map(|k| (
+                ((k % nx) as f32 + 0.5)/nx as f32,
+                ((k / nx) as f32 + 0.5)/ny as f32
+            ))),
+            Pattern::Jittered => out.extend((0..n).map(|k| (
+                ((k % nx) as f32 + rng.next_f32())/nx as f32,
+                ((k / nx) as f32 + rng.next_f32())/ny as f32
+            ))),
+            Pattern::Halton => {
+                let (sx, sy) = (rng.next_f32(), rng.next_f32());
+                out.extend((1..=n as u32).map(|k| (
+                    (radical_inverse(2, k) + sx).fract(),
+                    (radical_inverse(3, k) + sy).fract()
+                )))
+            },
+            Pattern::Sobol => {
+                let (sx, sy) = (rng.next_u32(), rng.next_u32());
+                out.extend((0..n as u32).map(|k| (unit(k.reverse_bits() ^ sx), unit(sobol2(k) ^ sy))))
+            }
+        }
+    }
+}
+
+/// Pixel reconstruction filter, weights samples by their distance to the pixel center.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3.
+    Mitchell
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("unknown filter '{}': expected box, tent, gaussian or mitchell", s))
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell"
+        })
+    }
+}
+
+impl Filter {
+    /// Support radius in pixels.
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0
+        }
+    }
+
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx)*self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        // Half-open, so a box sample on the edge between two pixels counts for only one of them
+        let r = self.radius();
+        if !(-r..r).contains(&d) {
+            return 0.0;
+        }
+        let d = d.abs();
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => r - d,
+            Filter::Gaussian => {
+                // Shifted down so the weight reaches zero at the radius
+                let alpha = 2.0;
+                (-alpha*d*d).exp() - (-alpha*r*r).exp()
+            },
+            Filter::Mitchell => {
+                let (b, c) = (1.0/3.0, 1.0/3.0);
+                let x = d;
+                (if x < 1.0 {
+                    (12.0 - 9.0*b - 6.0*c)*x*x*x + (-18.0 + 12.0*b + 6.0*c)*x*x + (6.0 - 2.0*b)
+                } else {
+                    (-b - 6.0*c)*x*x*x + (6.0*b + 30.0*c)*x*x + (-12.0*b - 48.0*c)*x + (8.0*b + 24.0*c)
+                })/6.0
+            }
+        }
+    }
+}
+
+/// Direction in the hemisphere around the unit vector `n`, with density proportional to the cosine to `n`.
+pub fn cosine_hemisphere(n: Vec3f, rng: &mut Rng) -> Vec3f {
+    let (u1, u2) = (rng.next_f32(), rng.next_f32());
+    let r = u1.sqrt();
+    let phi = 2.0*PI*u2;
+    let (t, b) = n.tangents();
+    (t*(r*phi.cos()) + b*(r*phi.sin()) + n*(1.0 - u1).max(0.0).sqrt()).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_stay_in_pixel() {
+        for pattern in [Pattern::Regular, Pattern::Jittered, Pattern::Halton, Pattern::Sobol] {
+            let mut rng = Rng::new(42);
+            let mut offsets = Vec::new();
+            pattern.offsets(16, &mut rng, &mut offsets);
+            assert_eq!(offsets.len(), 16);
+            for (x, y) in &offsets {
+                assert!((0.0..1.0).contains(x) && (0.0..1.0).contains(y), "{} {} {}", pattern, x, y);
+            }
+            // Stratified patterns put 4 of 16 samples in every quarter of the pixel
+            if pattern != Pattern::Halton {
+                let quarter = offsets.iter().filter(|(x, y)| *x < 0.5 && *y < 0.5).count();
+                assert_eq!(quarter, 4, "{}", pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn uneven_counts_stratify() {
+        assert_eq!(grid(6), (3, 2));
+        assert_eq!(grid(7), (7, 1));
+        for pattern in [Pattern::Regular, Pattern::Jittered] {
+            let mut offsets = vec![(2.0, 2.0); 20];
+            pattern.offsets(6, &mut Rng::new(7), &mut offsets);
+            assert_eq!(offsets.len(), 6);
+            // Every third of the width and half of the height holds the same number of samples
+            for c in 0..3 {
+                assert_eq!(offsets.iter().filter(|(x, _)| (x*3.0) as usize == c).count(), 2, "{}", pattern);
+            }
+            assert_eq!(offsets.iter().filter(|(_, y)| *y < 0.5).count(), 3, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn filters_vanish_at_radius() {
+        for filter in [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+            assert!(filter.weight(0.0, 0.0) > 0.0);
+            assert_eq!(filter.weight(filter.radius(), 0.0), 0.0);
+        }
+        // A sample on the edge between two pixels belongs to the one on its right
+        assert_eq!(Filter::Box.weight(-0.5, 0.0), 1.0);
+        assert_eq!(Filter::Box.weight(0.5, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,973 @@
+//! The scene file format, a list of items with their properties in braces:
+//!
+//! ```text
+//! camera { width 1024 height 768 position 0 2 5 target 0 0 -16 fov 60 }
+//! render { depth 4 samples 16 shadow_samples 8 pattern jittered filter mitchell }
+//! material ivory { diffuse_color 0.4 0.4 0.3 albedo 0.6 0.3 0.1 0 specular_exp 50 }
+//! material gold { diffuse_color 1 0.78 0.34 metallic 1 roughness 0.3 }
+//! material tiles { diffuse_color checker { even 1 1 1 odd image "marble.ppm" scale 8 } }
+//! material oak { diffuse_color wood { scale 2 seed 7 basis simplex octaves 3 stop 0 0.5 0.3 0.1 stop 1 0.3 0.15 0.05 } }
+//! sphere { center -3 0 -16 radius 2 material ivory }
+//! fireball { center 0 -2 -12 radius 1.5 amplitude 0.3 frequency 1.5 seed 7 material ivory }
+//! light { position -20 20 20 intensity 1.5 }
+//! light { position 0 10 -16 intensity 1 radius 0.5 color 1 0.9 0.8 falloff inverse_square }
+//! sun { direction -1 -2 -1 intensity 0.5 }
+//! spot { position 0 8 -16 direction 0 -1 0 inner 15 outer 25 intensity 2 }
+//! kaboom { radius 1.2 amplitude 0.8 steps 512 epsilon 0.001 relaxation 1.2 penumbra 16 light { position 0 10 10 } }
+//! kaboom { volume { step 0.01 extinction 6 } }
+//! ```
+//!
+//! # Materials
+//!
+//! Materials with `metallic` or `roughness` use the physically based model,
+//! with `diffuse_color` as the base color. `absorption` tints light
+//! travelling through transparent materials, the more the longer the path
+//! inside. Both take a texture wherever a color is expected.
+//!
+//! # Lights
+//!
+//! Lights and spots are points unless they have a `radius` (sphere), `normal`
+//! and `radius` (disk) or `edge_u` and `edge_v` (rectangle centered on the
+//! position). Spot cone angles are half-angles in degrees, `inner` below
+//! `outer` and neither past 90.
+//!
+//! # Kaboom
+//!
+//! The `kaboom` block sets up the fireball of kaboom mode. Besides the keys
+//! above it takes `seed`, `basis`, `center`, `background`, `time`,
+//! `max_dist`, `bisection`, `ambient`, `combine` (`add` or `floor`),
+//! `occlusion` and `occlusion_step`.
+//! Lights in it replace the default one, and a `volume` in it renders the
+//! fireball as a glowing medium.
+//!
+//! # Syntax
+//!
+//! `#` starts a comment outside of quoted strings, and `\"` and `\\` stand
+//! for a quote and a backslash inside them. Relative paths start from the
+//! directory the file was loaded from. Materials with procedural textures
+//! cannot be written, they and their objects are replaced by a comment.
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::bvh::Aabb;
+use crate::camera::{Camera, Projection};
+use crate::geometry::{Vec3f, Vec4f};
+use crate::hittable::Hittable;
+use crate::material::{Material, Model};
+use crate::mesh::Mesh;
+use crate::noise::Noise;
+use crate::plane::{Disk, Plane, Rect};
+use crate::march::{Kaboom, Volume};
+use crate::ppm;
+use crate::light::{Falloff, Light, LightKind, LightShape};
+use crate::render::{Scene, View};
+use crate::sampling::{Filter, Pattern};
+use crate::sdf::{self, Sdf, SdfObject};
+use crate::sphere::Sphere;
+use crate::texture::{Gradient, NoisePattern, NoiseTexture, Texture};
+
+/// Syntax error in a scene description, positions are 1-based.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::new(ErrorKind::InvalidData, e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Sphere { center: Vec3f, radius: f32 },
+    Plane { point: Vec3f, normal: Vec3f },
+    Rect { corner: Vec3f, edge_u: Vec3f, edge_v: Vec3f },
+    Disk { center: Vec3f, normal: Vec3f, radius: f32 },
+    Mesh { path: String },
+    /// Sphere with its surface displaced by seeded noise, ray marched as a distance field.
+    Fireball { center: Vec3f, radius: f32, amplitude: f32, frequency: f32, seed: u32 }
+}
+
+/// Object placed in the scene, materials are indices into `SceneFile::materials`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub shape: Shape,
+    pub material: usize
+}
+
+/// Everything needed to render a raytraced image or the kaboom fireball, in
+/// a form that can be read from and written back to text.
+pub struct SceneFile {
+    pub width: usize,
+    pub height: usize,
+    pub camera: Camera,
+    pub depth: usize,
+    pub samples: usize,
+    pub shadow_samples: usize,
+    pub pattern: Pattern,
+    pub filter: Filter,
+    pub materials: Vec<(String, Material)>,
+    pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    pub kaboom: Kaboom,
+    /// Directory that relative mesh and image paths are resolved against,
+    /// the one the file was loaded from. Empty for the current directory.
+    pub base: PathBuf
+}
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            camera: Camera::default(),
+            depth: 4,
+            samples: 1,
+            shadow_samples: 1,
+            pattern: Pattern::Regular,
+            filter: Filter::Box,
+            materials: Vec::new(),
+            objects: Vec::new(),
+            lights: Vec::new(),
+            kaboom: Kaboom::default(),
+            base: PathBuf::new()
+        }
+    }
+}
+
+impl SceneFile {
+    pub fn load(p: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(p).map_err(|e| Error::new(e.kind(), format!("{}: {}", p, e)))?;
+        let base = Path::new(p).parent().unwrap_or(Path::new(""));
+        Self::parse_in(&text, base).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}:{}", p, e)))
+    }
+
+    pub fn save(&self, p: &str) -> Result<(), Error> {
+        fs::write(p, self.to_string())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        Self::parse_in(text, Path::new(""))
+    }
+
+    /// Parses a file whose relative paths start from `base`.
+    pub fn parse_in(text: &str, base: &Path) -> Result<Self, ParseError> {
+        let mut p = Parser::new(text, base)?;
+        let mut file = SceneFile { base: base.to_path_buf(), ..SceneFile::default() };
+        while let Some(item) = p.next() {
+            match item.text {
+                "camera" => {
+                    let mut fov = None;
+                    let mut extent = None;
+                    let mut orthographic = false;
+                    let c = &mut file.camera;
+                    p.block(|p, key| {
+                        match key.text {
+                            "width" => file.width = p.positive()?,
+                            "height" => file.height = p.positive()?,
+                            "position" => c.position = p.vec3()?,
+                            "target" => c.target = p.vec3()?,
+                            "up" => c.up = p.vec3()?,
+                            "roll" => c.roll = p.number()?.to_radians(),
+                            "fov" => fov = Some(p.number()?.to_radians()),
+                            "extent" => extent = Some(p.number()?),
+                            "projection" => {
+                                let t = p.word()?;
+                                orthographic = match t.text {
+                                    "perspective" => false,
+                                    "orthographic" => true,
+                                    _ => return Err(t.error(format!(
+                                        "unknown projection '{}': expected perspective or orthographic", t.text)))
+                                };
+                            },
+                            _ => return Err(key.unknown())
+                        }
+                        Ok(())
+                    })?;
+                    c.projection = match (orthographic, c.projection) {
+                        (false, Projection::Perspective { fov: f }) => Projection::Perspective { fov: fov.unwrap_or(f) },
+                        (false, _) => Projection::Perspective { fov: fov.unwrap_or(PI / 3.0) },
+                        (true, Projection::Orthographic { height }) => Projection::Orthographic { height: extent.unwrap_or(height) },
+                        (true, _) => Projection::Orthographic { height: extent.unwrap_or(10.0) }
+                    };
+                    if (c.target - c.position).norm() == 0.0 || (c.target - c.position).cross(c.up).norm() == 0.0 {
+                        return Err(item.error("camera target must differ from its position and not lie along the up vector".to_string()));
+                    }
+                },
+                "render" => p.block(|p, key| {
+                    match key.text {
+                        "depth" => file.depth = p.integer()?,
+                        "samples" => file.samples = p.integer()?,
+                        "shadow_samples" => file.shadow_samples = p.integer()?,
+                        "pattern" => file.pattern = p.parse()?,
+                        "filter" => file.filter = p.parse()?,
+                        _ => return Err(key.unknown())
+                    }
+                    Ok(())
+                })?,
+                "kaboom" => {
+                    let mut lights = Vec::new();
+                    p.block(|p, key| {
+                        let k = &mut file.kaboom;
+                        match key.text {
+                            "radius" => k.radius = p.positive()?,
+                            "amplitude" => k.amplitude = p.number_in(0.0, f32::INFINITY)?,
+                            "seed" => k.noise.seed = p.integer()?,
+                            "basis" => k.noise.basis = p.parse()?,
+                            "steps" => k.tracer.max_steps = p.positive()?,
+                            "epsilon" => k.tracer.epsilon = p.positive()?,
+                            "max_dist" => k.tracer.max_dist = p.positive()?,
+                            "relaxation" => k.tracer.relaxation = p.number_in(1.0, 2.0)?,
+                            "bisection" => k.tracer.bisection = p.integer()?,
+                            "center" => k.center = p.vec3()?,
+                            "ambient" => k.shading.ambient = p.number()?,
+                            "combine" => k.shading.combine = p.parse()?,
+                            "penumbra" => k.shading.penumbra = p.number_in(0.0, f32::INFINITY)?,
+                            "occlusion" => k.shading.occlusion = p.integer()?,
+                            "occlusion_step" => k.shading.occlusion_step = p.number()?,
+                            "light" | "sun" | "spot" => lights.push(parse_light(p, key)?),
+                            "background" => k.background = p.vec3()?,
+                            "time" => k.time = p.number_in(0.0, f32::INFINITY)?,
+                            "volume" => {
+                                let mut v = Volume::default();
+                                p.block(|p, key| {
+                                    match key.text {
+                                        "step" => v.step = p.positive()?,
+                                        "extinction" => v.extinction = p.positive()?,
+                                        _ => return Err(key.unknown())
+                                    }
+                                    Ok(())
+                                })?;
+                                k.volume = Some(v);
+                            },
+                            _ => return Err(key.unknown())
+                        }
+                        Ok(())
+                    })?;
+                    // Lights given here replace the default one
+                    if !lights.is_empty() {
+                        file.kaboom.shading.lights = lights;
+                    }
+                },
+                "material" => {
+                    let name = p.word()?;
+                    if file.material(name.text).is_some() {
+                        return Err(name.error(format!("material '{}' is already defined", name.text)));
+                    }
+                    let mut m = Material::default();
+                    let (mut metallic, mut roughness) = (None, None);
+                    p.block(|p, key| {
+                        match key.text {
+                            "diffuse_color" => m.diffuse_color = p.texture()?,
+                            "albedo" => m.albedo = Vec4f::new(p.number()?, p.number()?, p.number()?, p.number()?),
+                            "specular_exp" => m.specular_exp = p.number()?,
+                            "refractive_index" => m.refractive_index = p.number()?,
+                            "absorption" => m.absorption = p.texture()?,
+                            "metallic" => metallic = Some(p.number()?),
+                            "roughness" => roughness = Some(p.number()?),
+                            _ => return Err(key.unknown())
+                        }
+                        Ok(())
+                    })?;
+                    if metallic.is_some() || roughness.is_some() {
+                        m.model = Model::MetallicRoughness { metallic: metallic.unwrap_or(0.0), roughness: roughness.unwrap_or(0.5) };
+                    }
+                    file.materials.push((name.text.to_string(), m));
+                },
+                "light" | "sun" | "spot" => {
+                    let light = parse_light(&mut p, item)?;
+                    file.lights.push(light);
+                },
+                "sphere" | "plane" | "rect" | "disk" | "mesh" | "fireball" => {
+                    let object = file.parse_object(&mut p, item)?;
+                    file.objects.push(object);
+                },
+                _ => return Err(item.error(format!("expected a scene item, found '{}'", item.text)))
+            }
+        }
+        Ok(file)
+    }
+
+    fn parse_object(&self, p: &mut Parser, item: Token) -> Result<Object, ParseError> {
+        let mut shape = match item.text {
+            "sphere" => Shape::Sphere { center: Vec3f::zero(), radius: 1.0 },
+            "plane" => Shape::Plane { point: Vec3f::zero(), normal: Vec3f::new(0.0, 1.0, 0.0) },
+            "rect" => Shape::Rect {
+                corner: Vec3f::zero(),
+                edge_u: Vec3f::new(1.0, 0.0, 0.0),
+                edge_v: Vec3f::new(0.0, 1.0, 0.0)
+            },
+            "disk" => Shape::Disk { center: Vec3f::zero(), normal: Vec3f::new(0.0, 1.0, 0.0), radius: 1.0 },
+            "fireball" => Shape::Fireball { center: Vec3f::zero(), radius: 1.0, amplitude: 0.25, frequency: 2.0, seed: 0 },
+            _ => Shape::Mesh { path: String::new() }
+        };
+        let mut material = None;
+        p.block(|p, key| {
+            match (&mut shape, key.text) {
+                (_, "material") => {
+                    let name = p.word()?;
+                    material = Some(self.material(name.text).ok_or_else(|| name.error(format!("unknown material '{}'", name.text)))?);
+                },
+                (Shape::Sphere { center, .. }, "center") | (Shape::Disk { center, .. }, "center")
+                    | (Shape::Fireball { center, .. }, "center") => *center = p.vec3()?,
+                (Shape::Sphere { radius, .. }, "radius") | (Shape::Disk { radius, .. }, "radius")
+                    | (Shape::Fireball { radius, .. }, "radius") => *radius = p.positive()?,
+                (Shape::Fireball { amplitude, .. }, "amplitude") => *amplitude = p.number_in(0.0, f32::INFINITY)?,
+                (Shape::Fireball { frequency, .. }, "frequency") => *frequency = p.positive()?,
+                (Shape::Fireball { seed, .. }, "seed") => *seed = p.integer()?,
+                (Shape::Plane { normal, .. }, "normal") | (Shape::Disk { normal, .. }, "normal") => *normal = p.vec3()?,
+                (Shape::Plane { point, .. }, "point") => *point = p.vec3()?,
+                (Shape::Rect { corner, .. }, "corner") => *corner = p.vec3()?,
+                (Shape::Rect { edge_u, .. }, "edge_u") => *edge_u = p.vec3()?,
+                (Shape::Rect { edge_v, .. }, "edge_v") => *edge_v = p.vec3()?,
+                (Shape::Mesh { path }, "path") => *path = p.string()?,
+                _ => return Err(key.unknown())
+            }
+            Ok(())
+        })?;
+        match shape {
+            Shape::Rect { edge_u, edge_v, .. } if edge_u.cross(edge_v).norm() == 0.0 =>
+                return Err(item.error("rect edges must be non-zero and not parallel".to_string())),
+            Shape::Plane { normal, .. } | Shape::Disk { normal, .. } if normal.norm() == 0.0 =>
+                return Err(item.error(format!("{} normal must be non-zero", item.text))),
+            _ => ()
+        }
+        let material = material.ok_or_else(|| item.error(format!("{} has no material", item.text)))?;
+        Ok(Object { shape, material })
+    }
+
+    fn material(&self, name: &str) -> Option<usize> {
+        self.materials.iter().position(|(n, _)| n == name)
+    }
+
+    pub fn view(&self) -> View {
+        View::from_camera(self.width, self.height, self.camera)
+            .with_depth(self.depth)
+            .with_samples(self.samples)
+            .with_shadow_samples(self.shadow_samples)
+            .with_pattern(self.pattern)
+            .with_filter(self.filter)
+    }
+
+    /// Builds the renderable scene, meshes are loaded from disk at this point.
+    pub fn scene(&self) -> Result<Scene<'_>, Error> {
+        let mut objects: Vec<Box<dyn Hittable + '_>> = Vec::with_capacity(self.objects.len());
+        for o in &self.objects {
+            let m = &self.materials[o.material].1;
+            objects.push(match &o.shape {
+                Shape::Sphere { center, radius } => Box::new(Sphere::new(*center, *radius, m)),
+                Shape::Plane { point, normal } => Box::new(Plane::new(*point, *normal, m)),
+                Shape::Rect { corner, edge_u, edge_v } => Box::new(Rect::new(*corner, *edge_u, *edge_v, m)),
+                Shape::Disk { center, normal, radius } => Box::new(Disk::new(*center, *normal, *radius, m)),
+                Shape::Mesh { path } => {
+                    let mesh = Mesh::load_obj(self.base.join(path), m)
+                        .map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))?;
+                    Box::new(mesh)
+                },
+                Shape::Fireball { center, radius, amplitude, frequency, seed } => {
+                    let noise = Noise::new(*seed).with_frequency(*frequency);
+                    let field = sdf::Sphere::new(*radius).displace(noise, *amplitude).translate(*center);
+                    let r = Vec3f::one()*(radius + amplitude.abs());
+                    Box::new(SdfObject::new(field, Aabb::new(*center - r, *center + r), m))
+                }
+            });
+        }
+        Ok(Scene::new(objects, self.lights.clone()))
+    }
+}
+
+fn parse_light(p: &mut Parser, item: Token) -> Result<Light, ParseError> {
+    let mut light = Light::new(Vec3f::zero(), 1.0);
+    let mut direction = Vec3f::new(0.0, -1.0, 0.0);
+    let (mut inner, mut outer) = (20f32.to_radians(), 30f32.to_radians());
+    let (mut radius, mut normal, mut edge_u, mut edge_v) = (None, None, None, None);
+    p.block(|p, key| {
+        match (item.text, key.text) {
+            (_, "intensity") => light.intensity = p.number()?,
+            (_, "color") => light.color = p.vec3()?,
+            ("sun" | "spot", "direction") => direction = p.vec3()?,
+            ("spot", "inner") => inner = p.number()?.to_radians(),
+            ("spot", "outer") => outer = p.number()?.to_radians(),
+            ("light" | "spot", "position") => light.pos = p.vec3()?,
+            ("light" | "spot", "falloff") => {
+                let kind = p.word()?;
+                light.falloff = match kind.text {
+                    "none" => Falloff::None,
+                    "inverse_square" => Falloff::InverseSquare,
+                    "custom" => Falloff::Custom { constant: p.number()?, linear: p.number()?, quadratic: p.number()? },
+                    _ => return Err(kind.error(format!(
+                        "unknown falloff '{}': expected none, inverse_square or custom", kind.text)))
+                };
+            },
+            ("light" | "spot", "radius") => radius = Some(p.number()?),
+            ("light" | "spot", "normal") => normal = Some(p.vec3()?),
+            ("light" | "spot", "edge_u") => edge_u = Some(p.vec3()?),
+            ("light" | "spot", "edge_v") => edge_v = Some(p.vec3()?),
+            _ => return Err(key.unknown())
+        }
+        Ok(())
+    })?;
+    if normal.is_some_and(|n| n.norm() == 0.0) {
+        return Err(item.error(format!("{} normal must be non-zero", item.text)));
+    }
+    if item.text != "light" && direction.norm() == 0.0 {
+        return Err(item.error(format!("{} direction must be non-zero", item.text)));
+    }
+    light.shape = match (radius, normal, edge_u, edge_v) {
+        (None, None, None, None) => LightShape::Point,
+        (Some(radius), None, None, None) => LightShape::Sphere { radius },
+        (Some(radius), Some(normal), None, None) => LightShape::Disk { normal: normal.normalize(), radius },
+        (None, None, Some(edge_u), Some(edge_v)) => LightShape::Rect { edge_u, edge_v },
+        _ => return Err(item.error(format!("{} needs a radius, a normal and radius, or edge_u and edge_v", item.text)))
+    };
+    light.kind = match item.text {
+        "sun" => LightKind::Directional { direction: direction.normalize() },
+        "spot" if !(0.0 <= inner && inner < outer && outer <= PI/2.0) =>
+            return Err(item.error("spot angles must satisfy 0 <= inner < outer <= 90".to_string())),
+        "spot" => LightKind::Spot { direction: direction.normalize(), inner, outer },
+        _ => LightKind::Omni
+    };
+    Ok(light)
+}
+
+/// Angles are stored in radians but written in degrees, rounded so that
+/// parsing and writing a file again gives the same text.
+fn degrees(radians: f32) -> f32 {
+    (radians.to_degrees()*1e4).round()/1e4
+}
+
+struct V3(Vec3f);
+
+impl fmt::Display for V3 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+/// A string in quotes, with backslashes escaping quotes and themselves.
+pub struct Quoted<'a>(pub &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self.0.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        out.extend(if c == '\\' { chars.next() } else { Some(c) });
+    }
+    out
+}
+
+/// A light as a scene item, on one line.
+struct LightItem<'a>(&'a Light);
+
+impl fmt::Display for LightItem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let l = self.0;
+        match l.kind {
+            LightKind::Omni => write!(f, "light {{ position {}", V3(l.pos))?,
+            LightKind::Directional { direction } => write!(f, "sun {{ direction {}", V3(direction))?,
+            LightKind::Spot { direction, inner, outer } => write!(f, "spot {{ position {} direction {} inner {} outer {}",
+                V3(l.pos), V3(direction), degrees(inner), degrees(outer))?
+        }
+        write!(f, " intensity {}", l.intensity)?;
+        if l.color != Vec3f::one() {
+            write!(f, " color {}", V3(l.color))?;
+        }
+        if let LightKind::Directional { .. } = l.kind {
+            return f.write_str(" }");
+        }
+        match l.falloff {
+            Falloff::None => (),
+            Falloff::InverseSquare => write!(f, " falloff inverse_square")?,
+            Falloff::Custom { constant, linear, quadratic } =>
+                write!(f, " falloff custom {} {} {}", constant, linear, quadratic)?
+        }
+        match l.shape {
+            LightShape::Point => (),
+            LightShape::Sphere { radius } => write!(f, " radius {}", radius)?,
+            LightShape::Rect { edge_u, edge_v } => write!(f, " edge_u {} edge_v {}", V3(edge_u), V3(edge_v))?,
+            LightShape::Disk { normal, radius } => write!(f, " normal {} radius {}", V3(normal), radius)?
+        }
+        f.write_str(" }")
+    }
+}
+
+impl fmt::Display for SceneFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = &self.camera;
+        write!(f, "camera {{ width {} height {}", self.width, self.height)?;
+        write!(f, " position {} target {} up {}", V3(c.position), V3(c.target), V3(c.up))?;
+        if c.roll != 0.0 {
+            write!(f, " roll {}", degrees(c.roll))?;
+        }
+        match c.projection {
+            Projection::Perspective { fov } => writeln!(f, " fov {} }}", degrees(fov))?,
+            Projection::Orthographic { height } => writeln!(f, " projection orthographic extent {} }}", height)?
+        }
+        writeln!(f, "render {{ depth {} samples {} shadow_samples {} pattern {} filter {} }}",
+            self.depth, self.samples, self.shadow_samples, self.pattern, self.filter)?;
+        let procedural = |m: &Material| m.diffuse_color.is_procedural() || m.absorption.is_procedural();
+        for (name, m) in &self.materials {
+            if procedural(m) {
+                writeln!(f, "\n# material {} has a procedural texture and is left out, as are its objects", name)?;
+                continue;
+            }
+            writeln!(f, "\nmaterial {} {{", name)?;
+            writeln!(f, "    diffuse_color {}", m.diffuse_color)?;
+            writeln!(f, "    albedo {} {} {} {}", m.albedo[0], m.albedo[1], m.albedo[2], m.albedo[3])?;
+            writeln!(f, "    specular_exp {}", m.specular_exp)?;
+            writeln!(f, "    refractive_index {}", m.refractive_index)?;
+            if m.absorption.constant() != Some(Vec3f::zero()) {
+                writeln!(f, "    absorption {}", m.absorption)?;
+            }
+            if let Model::MetallicRoughness { metallic, roughness } = m.model {
+                writeln!(f, "    metallic {}", metallic)?;
+                writeln!(f, "    roughness {}", roughness)?;
+            }
+            writeln!(f, "}}")?;
+        }
+        if !self.objects.is_empty() {
+            writeln!(f)?;
+        }
+        for o in self.objects.iter().filter(|o| !procedural(&self.materials[o.material].1)) {
+            match &o.shape {
+                Shape::Sphere { center, radius } =>
+                    write!(f, "sphere {{ center {} radius {}", V3(*center), radius)?,
+                Shape::Plane { point, normal } =>
+                    write!(f, "plane {{ point {} normal {}", V3(*point), V3(*normal))?,
+                Shape::Rect { corner, edge_u, edge_v } =>
+                    write!(f, "rect {{ corner {} edge_u {} edge_v {}", V3(*corner), V3(*edge_u), V3(*edge_v))?,
+                Shape::Disk { center, normal, radius } =>
+                    write!(f, "disk {{ center {} normal {} radius {}", V3(*center), V3(*normal), radius)?,
+                Shape::Mesh { path } =>
+                    write!(f, "mesh {{ path {}", Quoted(path))?,
+                Shape::Fireball { center, radius, amplitude, frequency, seed } =>
+                    write!(f, "fireball {{ center {} radius {} amplitude {} frequency {} seed {}", V3(*center), radius, amplitude, frequency, seed)?
+            }
+            write!(f, " material {}", self.materials[o.material].0)?;
+            writeln!(f, " }}")?;
+        }
+        if !self.lights.is_empty() {
+            writeln!(f)?;
+        }
+        for l in &self.lights {
+            writeln!(f, "{}", LightItem(l))?;
+        }
+        let k = &self.kaboom;
+        if *k != Kaboom::default() {
+            let t = &k.tracer;
+            writeln!(f, "\nkaboom {{ radius {} amplitude {} steps {} epsilon {} max_dist {} relaxation {} bisection {}",
+                k.radius, k.amplitude, t.max_steps, t.epsilon, t.max_dist, t.relaxation, t.bisection)?;
+            write!(f, "    seed {} basis {} center {} background {} time {}", k.noise.seed, k.noise.basis, V3(k.center), V3(k.background), k.time)?;
+            let s = &k.shading;
+            write!(f, "\n    ambient {} combine {} penumbra {} occlusion {} occlusion_step {}", s.ambient, s.combine, s.penumbra, s.occlusion, s.occlusion_step)?;
+            for l in &s.lights {
+                write!(f, "\n    {}", LightItem(l))?;
+            }
+            if let Some(v) = k.volume {
+                write!(f, "\n    volume {{ step {} extinction {} }}", v.step, v.extinction)?;
+            }
+            writeln!(f, " }}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'s> {
+    text: &'s str,
+    quoted: bool,
+    line: usize,
+    col: usize
+}
+
+impl<'s> Token<'s> {
+    fn error(&self, message: String) -> ParseError {
+        ParseError { line: self.line, col: self.col, message }
+    }
+
+    fn unknown(&self) -> ParseError {
+        self.error(format!("unknown property '{}'", self.text))
+    }
+}
+
+struct Parser<'s> {
+    tokens: Vec<Token<'s>>,
+    pos: usize,
+    end: (usize, usize),
+    /// Directory that image paths are relative to.
+    base: &'s Path
+}
+
+impl<'s> Parser<'s> {
+    fn new(text: &'s str, base: &'s Path) -> Result<Self, ParseError> {
+        let mut tokens = Vec::new();
+        let mut end = (1, 1);
+        for (l, line) in text.lines().enumerate() {
+            let mut cut = line.len();
+            let mut chars = line.char_indices().peekable();
+            while let Some(&(i, c)) = chars.peek() {
+                let col = line[..i].chars().count() + 1;
+                if c == '#' {
+                    cut = i;
+                    break;
+                } else if c.is_whitespace() {
+                    chars.next();
+                } else if c == '{' || c == '}' {
+                    chars.next();
+                    tokens.push(Token { text: &line[i..i + 1], quoted: false, line: l + 1, col });
+                } else if c == '"' {
+                    // The text keeps its escapes, `Parser::string` removes them
+                    let bytes = line.as_bytes();
+                    let mut close = i + 1;
+                    while close < bytes.len() && bytes[close] != b'"' {
+                        close += if bytes[close] == b'\\' { 2 } else { 1 };
+                    }
+                    if close >= bytes.len() {
+                        return Err(ParseError { line: l + 1, col, message: "unterminated string".to_string() });
+                    }
+                    tokens.push(Token { text: &line[i + 1..close], quoted: true, line: l + 1, col });
+                    while chars.next_if(|&(j, _)| j <= close).is_some() {}
+                } else {
+                    let mut j = line.len();
+                    while let Some(&(k, c)) = chars.peek() {
+                        if c.is_whitespace() || c == '{' || c == '}' || c == '"' {
+                            j = k;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    tokens.push(Token { text: &line[i..j], quoted: false, line: l + 1, col });
+                }
+            }
+            end = (l + 1, line[..cut].chars().count() + 1);
+        }
+        Ok(Self { tokens, pos: 0, end, base })
+    }
+
+    fn next(&mut self) -> Option<Token<'s>> {
+        let t = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        t
+    }
+
+    fn expect_any(&mut self, what: &str) -> Result<Token<'s>, ParseError> {
+        self.next().ok_or_else(|| ParseError {
+            line: self.end.0,
+            col: self.end.1,
+            message: format!("expected {}, found end of file", what)
+        })
+    }
+
+    fn symbol(&mut self, s: &str) -> Result<(), ParseError> {
+        let t = self.expect_any(&format!("'{}'", s))?;
+        if t.text != s || t.quoted {
+            return Err(t.error(format!("expected '{}', found '{}'", s, t.text)));
+        }
+        Ok(())
+    }
+
+    /// Parses `{ key values... }`, handing every key to `property`.
+    fn block<F>(&mut self, mut property: F) -> Result<(), ParseError>
+    where F: FnMut(&mut Self, Token<'s>) -> Result<(), ParseError> {
+        self.symbol("{")?;
+        loop {
+            let key = self.expect_any("a property or '}'")?;
+            if key.text == "}" && !key.quoted {
+                return Ok(());
+            }
+            property(self, key)?;
+        }
+    }
+
+    fn word(&mut self) -> Result<Token<'s>, ParseError> {
+        let t = self.expect_any("a name")?;
+        if t.quoted || t.text == "{" || t.text == "}" {
+            return Err(t.error(format!("expected a name, found '{}'", t.text)));
+        }
+        Ok(t)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let t = self.expect_any("a quoted string")?;
+        if !t.quoted {
+            return Err(t.error(format!("expected a quoted string, found '{}'", t.text)));
+        }
+        Ok(unescape(t.text))
+    }
+
+    fn number(&mut self) -> Result<f32, ParseError> {
+        let t = self.expect_any("a number")?;
+        t.text.parse().ok().filter(|_| !t.quoted)
+            .ok_or_else(|| t.error(format!("expected a number, found '{}'", t.text)))
+    }
+
+    fn positive<T: FromStr + PartialOrd + Default>(&mut self) -> Result<T, ParseError> {
+        let t = self.expect_any("a positive number")?;
+        t.text.parse().ok().filter(|v| !t.quoted && *v > T::default())
+            .ok_or_else(|| t.error(format!("expected a positive number, found '{}'", t.text)))
+    }
+
+    /// A number from `lo` to `hi`, both included.
+    fn number_in(&mut self, lo: f32, hi: f32) -> Result<f32, ParseError> {
+        let t = self.expect_any("a number")?;
+        t.text.parse().ok().filter(|v| !t.quoted && (lo..=hi).contains(v)).ok_or_else(|| t.error(if hi.is_finite() {
+            format!("expected a number from {} to {}, found '{}'", lo, hi, t.text)
+        } else {
+            format!("expected a number of at least {}, found '{}'", lo, t.text)
+        }))
+    }
+
+    fn integer<T: FromStr>(&mut self) -> Result<T, ParseError> {
+        let t = self.expect_any("an integer")?;
+        t.text.parse().ok().filter(|_| !t.quoted)
+            .ok_or_else(|| t.error(format!("expected a non-negative integer, found '{}'", t.text)))
+    }
+
+    fn parse<T: FromStr<Err = String>>(&mut self) -> Result<T, ParseError> {
+        let t = self.word()?;
+        t.text.parse().map_err(|e| t.error(e))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3f, ParseError> {
+        Ok(Vec3f::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    /// Either three numbers for a constant color, `checker { even <texture>
+    /// odd <texture> scale <n> }`, `image "file.ppm"` or a noise pattern like
+    /// `marble { scale <n> seed <n> basis <name> octaves <n> lacunarity <n> gain <n>
+    /// stop <t> <color> ... }`. Images are loaded
+    /// here, relative to `base`.
+    fn texture(&mut self) -> Result<Texture, ParseError> {
+        let t = self.tokens.get(self.pos).copied();
+        if t.is_some_and(|t| t.quoted || t.text.parse::<f32>().is_ok()) {
+            return Ok(Texture::Constant(self.vec3()?));
+        }
+        let t = self.word()?;
+        match t.text {
+            "checker" => {
+                let (mut even, mut odd, mut scale) = (Texture::Constant(Vec3f::one()), Texture::default(), 1.0);
+                self.block(|p, key| {
+                    match key.text {
+                        "even" => even = p.texture()?,
+                        "odd" => odd = p.texture()?,
+                        "scale" => scale = p.number()?,
+                        _ => return Err(key.unknown())
+                    }
+                    Ok(())
+                })?;
+                Ok(Texture::checker(even, odd, scale))
+            },
+            "image" => {
+                let path = self.expect_any("a quoted string")?;
+                if !path.quoted {
+                    return Err(path.error(format!("expected a quoted string, found '{}'", path.text)));
+                }
+                let text = unescape(path.text);
+                let image = ppm::load(self.base.join(&text)).map_err(|e| path.error(e.to_string()))?;
+                Ok(Texture::Image { path: text, image: Arc::new(image) })
+            },
+            name => match NoisePattern::ALL.into_iter().find(|n| n.name() == name) {
+                Some(pattern) => {
+                    let mut noise = NoiseTexture::new(pattern);
+                    let mut stops = Vec::new();
+                    self.block(|p, key| {
+                        match key.text {
+                            "scale" => noise.scale = p.number()?,
+                            "seed" => noise.noise.seed = p.integer()?,
+                            "basis" => noise.noise.basis = p.parse()?,
+                            "octaves" => noise.noise.octaves = p.integer::<usize>()?.max(1),
+                            "lacunarity" => noise.noise.lacunarity = p.number()?,
+                            "gain" => noise.noise.gain = p.number()?,
+                            "stop" => stops.push((p.number()?, p.vec3()?)),
+                            _ => return Err(key.unknown())
+                        }
+                        Ok(())
+                    })?;
+                    if !stops.is_empty() {
+                        noise.gradient = Gradient::new(stops);
+                    }
+                    Ok(Texture::Noise(noise))
+                },
+                None => Err(t.error(format!(
+                    "expected a color, checker, image, marble, wood, turbulence or clouds, found '{}'", name)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vec2f;
+    use crate::march::{Ambient, Tracer};
+    use crate::noise::Basis;
+
+    #[test]
+    fn round_trip() {
+        let file = SceneFile::parse(crate::DEFAULT_SCENE).unwrap();
+        assert_eq!(file.objects.len(), 5);
+        assert_eq!(file.lights.len(), 3);
+        let text = file.to_string();
+        let again = SceneFile::parse(&text).unwrap();
+        assert_eq!(again.to_string(), text);
+        assert_eq!(again.objects, file.objects);
+
+        let file = SceneFile::parse(include_str!("../scenes/fireball.scene")).unwrap();
+        assert!(matches!(file.objects[4].shape, Shape::Fireball { seed: 3, .. }));
+        assert_eq!(SceneFile::parse(&file.to_string()).unwrap().objects, file.objects);
+
+        let file = SceneFile::parse("material gold { diffuse_color 1 0.78 0.34 metallic 1 roughness 0.3 }\n\
+            light { position 0 5 0 radius 0.5 falloff custom 1 0 0.5 }\n\
+            light { edge_u 1 0 0 edge_v 0 0 1 color 1 0 0 }\n\
+            sun { direction 0 -1 0 intensity 0.5 }\n\
+            spot { position 0 4 0 direction 0 -1 0 inner 10 outer 20 }\n\
+            fireball { center 0 1 -5 amplitude 0.5 seed 9 material gold }").unwrap();
+        assert_eq!(file.lights[0].shape, LightShape::Sphere { radius: 0.5 });
+        assert_eq!(file.lights[2].kind, LightKind::Directional { direction: Vec3f::new(0.0, -1.0, 0.0) });
+        let again = SceneFile::parse(&file.to_string()).unwrap();
+        assert_eq!(again.lights, file.lights);
+        assert_eq!(again.objects, file.objects);
+        assert_eq!(again.materials[0].1.model, Model::MetallicRoughness { metallic: 1.0, roughness: 0.3 });
+
+        let file = SceneFile::parse("kaboom { radius 1 steps 64 background 0 0 0 }").unwrap();
+        let tracer = Tracer { max_steps: 64, ..Tracer::default() };
+        assert_eq!(file.kaboom, Kaboom { radius: 1.0, tracer, background: Vec3f::zero(), ..Kaboom::default() });
+        assert_eq!(SceneFile::parse(&file.to_string()).unwrap().kaboom, file.kaboom);
+        let file = SceneFile::parse("kaboom { penumbra 8 combine add light { position 1 2 3 } sun { direction 0 -1 0 } }").unwrap();
+        assert_eq!(file.kaboom.shading.penumbra, 8.0);
+        assert_eq!(file.kaboom.shading.combine, Ambient::Add);
+        assert_eq!(file.kaboom.shading.lights, vec![Light::new(Vec3f::new(1.0, 2.0, 3.0), 1.0), Light::directional(Vec3f::new(0.0, -1.0, 0.0), 1.0)]);
+        assert_eq!(SceneFile::parse(&file.to_string()).unwrap().kaboom, file.kaboom);
+        let file = SceneFile::parse("kaboom { seed 12 basis simplex volume { extinction 2 } }").unwrap();
+        assert_eq!(file.kaboom.noise, Noise { seed: 12, ..Kaboom::default().noise.with_basis(Basis::Simplex) });
+        assert_eq!(file.kaboom.volume, Some(Volume { extinction: 2.0, ..Volume::default() }));
+        assert_eq!(SceneFile::parse(&file.to_string()).unwrap().kaboom, file.kaboom);
+    }
+
+    #[test]
+    fn textures() {
+        // Images load from next to the scene, like meshes
+        let dir = std::env::temp_dir().join(format!("ray-rs-textures-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("green \"1\".ppm"), "P3 1 1 255 0 255 0").unwrap();
+        let text = "material t { diffuse_color checker { even 1 0 0 odd image \"green \\\"1\\\".ppm\" scale 4 } }";
+        let file = SceneFile::parse_in(text, &dir);
+        let missing = SceneFile::parse(text).err();
+        fs::remove_dir_all(&dir).unwrap();
+        let file = file.unwrap();
+        assert_eq!(missing.map(|e| (e.line, e.col)), Some((1, 59)));
+        let texture = &file.materials[0].1.diffuse_color;
+        assert_eq!(texture.eval(Vec2f::new(0.1, 0.1), Vec3f::zero()), Vec3f::new(1.0, 0.0, 0.0));
+        assert_eq!(texture.eval(Vec2f::new(0.3, 0.1), Vec3f::zero()), Vec3f::new(0.0, 1.0, 0.0));
+        assert!(file.to_string().contains(r#"odd image "green \"1\".ppm" scale 4"#));
+
+        let file = SceneFile::parse("material m { diffuse_color marble { scale 3 seed 4000000000 basis simplex octaves 6 gain 0.6 } }\n\
+            material w { diffuse_color wood { stop 0 1 0 0 stop 1 0 0 1 } }").unwrap();
+        let again = SceneFile::parse(&file.to_string()).unwrap();
+        assert_eq!(again.to_string(), file.to_string());
+        match (&file.materials[0].1.diffuse_color, &file.materials[1].1.diffuse_color) {
+            (Texture::Noise(marble), Texture::Noise(wood)) => {
+                assert_eq!(marble.noise, Noise::new(4_000_000_000).with_basis(Basis::Simplex).with_octaves(6).with_gain(0.6));
+                assert_eq!(wood.gradient.at(0.0), Vec3f::new(1.0, 0.0, 0.0));
+            },
+            t => panic!("{:?}", t)
+        }
+
+        let err = SceneFile::parse("material t { diffuse_color image \"missing.ppm\" }").err().unwrap();
+        assert_eq!((err.line, err.col), (1, 34));
+
+        // Code cannot be written, the material and its objects are left out
+        let mut file = SceneFile::parse("material a {}\nmaterial b {}\nsphere { material a }\nsphere { material b }").unwrap();
+        file.materials[0].1.diffuse_color = Texture::procedural(|uv, _| Vec3f::new(uv[0], uv[1], 0.0));
+        let again = SceneFile::parse(&file.to_string()).unwrap();
+        assert_eq!(again.materials.len(), 1);
+        assert_eq!(again.objects.len(), 1);
+    }
+
+    #[test]
+    fn strings_and_paths() {
+        let file = SceneFile::parse("material m {} # the only one\nmesh { path \"a \\\"b\\\" #1.obj\" material m }").unwrap();
+        assert_eq!(file.objects[0].shape, Shape::Mesh { path: "a \"b\" #1.obj".to_string() });
+        assert_eq!(SceneFile::parse(&file.to_string()).unwrap().objects, file.objects);
+
+        // Meshes load from next to the scene, wherever it is loaded from
+        let dir = std::env::temp_dir().join(format!("ray-rs-paths-{}", std::process::id()));
+        fs::create_dir_all(dir.join("models")).unwrap();
+        fs::write(dir.join("models/tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        fs::write(dir.join("tri.scene"), "material m {}\nmesh { path \"models/tri.obj\" material m }\n").unwrap();
+        let loaded = SceneFile::load(dir.join("tri.scene").to_str().unwrap()).map(|f| f.scene().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(loaded.unwrap());
+    }
+
+    #[test]
+    fn error_position() {
+        let err = SceneFile::parse("material red { diffuse_color 1 0 0 }\nsphere {\n  radius two material red }")
+            .err().unwrap();
+        assert_eq!((err.line, err.col), (3, 10));
+
+        let err = SceneFile::parse("sphere { radius 1 material blue }").err().unwrap();
+        assert_eq!((err.line, err.col), (1, 28));
+        assert_eq!(err.message, "unknown material 'blue'");
+
+        let err = SceneFile::parse("render { depth 4").err().unwrap();
+        assert_eq!((err.line, err.col), (1, 17));
+
+        let err = SceneFile::parse("material m {}\nrect { edge_u 1 0 0 edge_v 2 0 0 material m }").err().unwrap();
+        assert_eq!((err.line, err.col), (2, 1));
+
+        for angles in ["inner 30 outer 20", "inner 20 outer 100", "inner -5 outer 20"] {
+            let err = SceneFile::parse(&format!("spot {{ {} }}", angles)).err().unwrap();
+            assert_eq!((err.line, err.col), (1, 1), "{}", angles);
+        }
+
+        let err = SceneFile::parse("fireball { seed 4294967296 }").err().unwrap();
+        assert_eq!((err.line, err.col), (1, 17));
+        for key in ["radius 0", "amplitude -0.5", "frequency 0"] {
+            let err = SceneFile::parse(&format!("fireball {{ {} }}", key)).err().unwrap();
+            assert_eq!((err.line, err.col), (1, 12 + key.find(' ').unwrap() + 1), "{}", key);
+        }
+
+        for item in ["plane { normal 0 0 0 material m }", "disk { normal 0 0 0 material m }", "sun { direction 0 0 0 }",
+            "spot { direction 0 0 0 }", "light { radius 1 normal 0 0 0 }"] {
+            let err = SceneFile::parse(&format!("material m {{}}\n{}", item)).err().unwrap();
+            assert_eq!((err.line, err.col), (2, 1), "{}", item);
+        }
+        for (text, col) in [("sphere { radius 0 }", 17), ("disk { radius -1 }", 15), ("camera { width 0 }", 16), ("camera { height -2 }", 17)] {
+            let err = SceneFile::parse(text).err().unwrap();
+            assert_eq!((err.line, err.col), (1, col), "{}", text);
+        }
+
+        for key in ["radius 0", "amplitude -1", "steps 0", "epsilon -0.1", "relaxation 3", "penumbra -2"] {
+            let err = SceneFile::parse(&format!("kaboom {{ {} }}", key)).err().unwrap();
+            assert_eq!((err.line, err.col), (1, 10 + key.find(' ').unwrap() + 1), "{}", key);
+        }
+        for key in ["step 0", "extinction -4"] {
+            let err = SceneFile::parse(&format!("kaboom {{ volume {{ {} }} }}", key)).err().unwrap();
+            assert_eq!((err.line, err.col), (1, 19 + key.find(' ').unwrap() + 1), "{}", key);
+        }
+    }
+}
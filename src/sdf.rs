@@ -0,0 +1,681 @@
+use std::f32::consts::PI;
+
+use crate::bvh::Aabb;
+use crate::geometry::{Vec2f, Vec3f};
+use crate::hittable::{Hit, Hittable};
+use crate::march::Tracer;
+use crate::material::Material;
+use crate::noise::Noise;
+
+// Step for the finite differences of the default normal
+const NORMAL_EPS: f32 = 1e-3;
+
+/// Signed distance to a surface, negative inside. Primitives are centered on
+/// the origin, the domain operations move, turn and bend them into place.
+///
+/// ```
+/// use ray_rs::geometry::Vec3f;
+/// use ray_rs::sdf::{Cuboid, Sdf, Sphere};
+///
+/// let die = Cuboid::new(Vec3f::one())
+///     .intersection(Sphere::new(1.35))
+///     .subtraction(Sphere::new(0.3).translate(Vec3f::new(0.0, 1.0, 0.0)));
+/// assert!(die.dist(Vec3f::zero()) < 0.0);
+/// ```
+pub trait Sdf: Sync {
+    fn dist(&self, p: Vec3f) -> f32;
+
+    /// Unit gradient of the field, the surface normal near the surface.
+    fn normal(&self, p: Vec3f) -> Vec3f {
+        let axis = |i: usize| {
+            let mut e = Vec3f::zero();
+            e[i] = NORMAL_EPS;
+            self.dist(p + e) - self.dist(p - e)
+        };
+        Vec3f::new(axis(0), axis(1), axis(2)).normalize()
+    }
+
+    /// Upper bound on how fast the field changes per unit of distance, 1 for exact distances.
+    fn lipschitz(&self) -> f32 {
+        1.0
+    }
+
+    /// Safe step from `p`: has the sign of `dist` but is never further from
+    /// zero than the true distance to the surface.
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.dist(p)/self.lipschitz()
+    }
+
+    fn union<B: Sdf>(self, b: B) -> Union<Self, B> where Self: Sized {
+        Union(self, b)
+    }
+
+    fn intersection<B: Sdf>(self, b: B) -> Intersection<Self, B> where Self: Sized {
+        Intersection(self, b)
+    }
+
+    /// This shape with `b` carved out of it.
+    fn subtraction<B: Sdf>(self, b: B) -> Subtraction<Self, B> where Self: Sized {
+        Subtraction(self, b)
+    }
+
+    /// Union blended over a distance of about `k`.
+    fn smooth_union<B: Sdf>(self, b: B, k: f32) -> SmoothUnion<Self, B> where Self: Sized {
+        SmoothUnion { a: self, b, k }
+    }
+
+    fn translate(self, offset: Vec3f) -> Translate<Self> where Self: Sized {
+        Translate { inner: self, offset }
+    }
+
+    /// Turned by `angle` radians around `axis`, counterclockwise looking against it.
+    fn rotate(self, axis: Vec3f, angle: f32) -> Rotate<Self> where Self: Sized {
+        Rotate { inner: self, axis: axis.normalize(), angle }
+    }
+
+    /// Uniformly larger by `factor`. Panics unless `factor > 0`.
+    fn scale(self, factor: f32) -> Scale<Self> where Self: Sized {
+        assert!(factor > 0.0, "scale factor must be positive");
+        Scale { inner: self, factor }
+    }
+
+    /// Infinite copies every `period` along each axis, axes with a period of 0
+    /// are not repeated. The shape should fit within one cell.
+    fn repeat(self, period: Vec3f) -> Repeat<Self> where Self: Sized {
+        Repeat { inner: self, period }
+    }
+
+    /// Twisted around the Y axis by `rate` radians per unit of height. The
+    /// shape must lie within `radius` of the axis.
+    fn twist(self, rate: f32, radius: f32) -> Twist<Self> where Self: Sized {
+        Twist { inner: self, rate, radius }
+    }
+
+    /// Surface pushed out by `amplitude` times the noise, and pulled in where it is negative.
+    fn displace(self, noise: Noise, amplitude: f32) -> Displace<Self> where Self: Sized {
+        Displace { inner: self, noise, amplitude }
+    }
+}
+
+impl<S: Sdf + ?Sized> Sdf for Box<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        (**self).dist(p)
+    }
+
+    fn normal(&self, p: Vec3f) -> Vec3f {
+        (**self).normal(p)
+    }
+
+    fn lipschitz(&self) -> f32 {
+        (**self).lipschitz()
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        (**self).bound(p)
+    }
+}
+
+impl<S: Sdf + ?Sized> Sdf for &S {
+    fn dist(&self, p: Vec3f) -> f32 {
+        (**self).dist(p)
+    }
+
+    fn normal(&self, p: Vec3f) -> Vec3f {
+        (**self).normal(p)
+    }
+
+    fn lipschitz(&self) -> f32 {
+        (**self).lipschitz()
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        (**self).bound(p)
+    }
+}
+
+fn length2(x: f32, y: f32) -> f32 {
+    (x*x + y*y).sqrt()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sphere {
+    pub radius: f32
+}
+
+impl Sphere {
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+}
+
+impl Sdf for Sphere {
+    fn dist(&self, p: Vec3f) -> f32 {
+        p.norm() - self.radius
+    }
+}
+
+/// Axis aligned box, `half` is half of its size along each axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cuboid {
+    pub half: Vec3f
+}
+
+impl Cuboid {
+    pub fn new(half: Vec3f) -> Self {
+        Self { half }
+    }
+}
+
+fn cuboid(p: Vec3f, half: Vec3f) -> f32 {
+    let q = Vec3f::new(p[0].abs(), p[1].abs(), p[2].abs()) - half;
+    q.max(Vec3f::zero()).norm() + q[0].max(q[1].max(q[2])).min(0.0)
+}
+
+impl Sdf for Cuboid {
+    fn dist(&self, p: Vec3f) -> f32 {
+        cuboid(p, self.half)
+    }
+}
+
+/// Box with edges rounded off by `radius`, within the same `half` size.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RoundCuboid {
+    pub half: Vec3f,
+    pub radius: f32
+}
+
+impl RoundCuboid {
+    pub fn new(half: Vec3f, radius: f32) -> Self {
+        Self { half, radius }
+    }
+}
+
+impl Sdf for RoundCuboid {
+    fn dist(&self, p: Vec3f) -> f32 {
+        cuboid(p, self.half - Vec3f::one()*self.radius) - self.radius
+    }
+}
+
+/// Ring lying in the XZ plane.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Torus {
+    /// Distance from the center to the middle of the tube.
+    pub major: f32,
+    /// Radius of the tube.
+    pub minor: f32
+}
+
+impl Torus {
+    pub fn new(major: f32, minor: f32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl Sdf for Torus {
+    fn dist(&self, p: Vec3f) -> f32 {
+        length2(length2(p[0], p[2]) - self.major, p[1]) - self.minor
+    }
+}
+
+/// Points within `radius` of the segment from `a` to `b`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Capsule {
+    pub a: Vec3f,
+    pub b: Vec3f,
+    pub radius: f32
+}
+
+impl Capsule {
+    pub fn new(a: Vec3f, b: Vec3f, radius: f32) -> Self {
+        Self { a, b, radius }
+    }
+}
+
+impl Sdf for Capsule {
+    fn dist(&self, p: Vec3f) -> f32 {
+        let (pa, ba) = (p - self.a, self.b - self.a);
+        let h = ((pa*ba)/(ba*ba)).clamp(0.0, 1.0);
+        (pa - ba*h).norm() - self.radius
+    }
+}
+
+/// Capped cylinder along the Y axis, reaching `half_height` above and below the origin.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cylinder {
+    pub radius: f32,
+    pub half_height: f32
+}
+
+impl Cylinder {
+    pub fn new(radius: f32, half_height: f32) -> Self {
+        Self { radius, half_height }
+    }
+}
+
+impl Sdf for Cylinder {
+    fn dist(&self, p: Vec3f) -> f32 {
+        let d = Vec2f::new(length2(p[0], p[2]) - self.radius, p[1].abs() - self.half_height);
+        d[0].max(d[1]).min(0.0) + d.max(Vec2f::zero()).norm()
+    }
+}
+
+/// Solid cone standing on a disk of `radius` in the XZ plane, with its tip at `height` on the Y axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cone {
+    pub radius: f32,
+    pub height: f32
+}
+
+impl Cone {
+    pub fn new(radius: f32, height: f32) -> Self {
+        Self { radius, height }
+    }
+}
+
+impl Sdf for Cone {
+    fn dist(&self, p: Vec3f) -> f32 {
+        // Distance in the plane through the axis, to the base edge and to the slanted side
+        let h = self.height/2.0;
+        let q = Vec2f::new(length2(p[0], p[2]), p[1] - h);
+        let k1 = Vec2f::new(0.0, h);
+        let k2 = Vec2f::new(-self.radius, 2.0*h);
+        let rim = if q[1] < 0.0 { self.radius } else { 0.0 };
+        let ca = Vec2f::new(q[0] - q[0].min(rim), q[1].abs() - h);
+        let cb = q - k1 + k2*(((k1 - q)*k2)/(k2*k2)).clamp(0.0, 1.0);
+        let s = if cb[0] < 0.0 && ca[1] < 0.0 { -1.0 } else { 1.0 };
+        s*(ca*ca).min(cb*cb).sqrt()
+    }
+}
+
+/// Everything below the plane through `normal*offset` with the given normal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3f,
+    pub offset: f32
+}
+
+impl Plane {
+    pub fn new(normal: Vec3f, offset: f32) -> Self {
+        Self { normal: normal.normalize(), offset }
+    }
+}
+
+impl Sdf for Plane {
+    fn dist(&self, p: Vec3f) -> f32 {
+        p*self.normal - self.offset
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Union<A, B>(pub A, pub B);
+
+impl<A: Sdf, B: Sdf> Sdf for Union<A, B> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.0.dist(p).min(self.1.dist(p))
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.0.lipschitz().max(self.1.lipschitz())
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.0.bound(p).min(self.1.bound(p))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Intersection<A, B>(pub A, pub B);
+
+impl<A: Sdf, B: Sdf> Sdf for Intersection<A, B> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.0.dist(p).max(self.1.dist(p))
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.0.lipschitz().max(self.1.lipschitz())
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.0.bound(p).max(self.1.bound(p))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Subtraction<A, B>(pub A, pub B);
+
+impl<A: Sdf, B: Sdf> Sdf for Subtraction<A, B> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.0.dist(p).max(-self.1.dist(p))
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.0.lipschitz().max(self.1.lipschitz())
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.0.bound(p).max(-self.1.bound(p))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SmoothUnion<A, B> {
+    pub a: A,
+    pub b: B,
+    pub k: f32
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        let (a, b) = (self.a.dist(p), self.b.dist(p));
+        if self.k <= 0.0 {
+            return a.min(b);
+        }
+        // Polynomial smooth minimum
+        let h = (self.k - (a - b).abs()).max(0.0)/self.k;
+        a.min(b) - h*h*self.k*0.25
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.a.lipschitz().max(self.b.lipschitz())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Translate<S> {
+    pub inner: S,
+    pub offset: Vec3f
+}
+
+impl<S: Sdf> Sdf for Translate<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.inner.dist(p - self.offset)
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.inner.lipschitz()
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.inner.bound(p - self.offset)
+    }
+}
+
+/// Rodrigues' rotation of `v` around the unit `axis`.
+fn rotate(v: Vec3f, axis: Vec3f, angle: f32) -> Vec3f {
+    let (sin, cos) = angle.sin_cos();
+    v*cos + axis.cross(v)*sin + axis*((axis*v)*(1.0 - cos))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rotate<S> {
+    pub inner: S,
+    pub axis: Vec3f,
+    pub angle: f32
+}
+
+impl<S: Sdf> Sdf for Rotate<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.inner.dist(rotate(p, self.axis, -self.angle))
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.inner.lipschitz()
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.inner.bound(rotate(p, self.axis, -self.angle))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Scale<S> {
+    pub inner: S,
+    pub factor: f32
+}
+
+impl<S: Sdf> Sdf for Scale<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.inner.dist(p*(1.0/self.factor))*self.factor
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.inner.lipschitz()
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.inner.bound(p*(1.0/self.factor))*self.factor
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Repeat<S> {
+    pub inner: S,
+    pub period: Vec3f
+}
+
+impl<S: Sdf> Repeat<S> {
+    fn cell(&self, p: Vec3f) -> Vec3f {
+        let mut q = p;
+        for i in 0..3 {
+            let c = self.period[i];
+            if c > 0.0 {
+                q[i] -= c*(p[i]/c).round();
+            }
+        }
+        q
+    }
+}
+
+impl<S: Sdf> Sdf for Repeat<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.inner.dist(self.cell(p))
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.inner.lipschitz()
+    }
+
+    fn bound(&self, p: Vec3f) -> f32 {
+        self.inner.bound(self.cell(p))
+    }
+}
+
+/// Bends space, so distances are only bounds that shrink as the rate and
+/// radius grow.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Twist<S> {
+    pub inner: S,
+    pub rate: f32,
+    /// How far the shape reaches from the axis.
+    pub radius: f32
+}
+
+impl<S: Sdf> Sdf for Twist<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        // Further out the twist sweeps points faster than the bound allows,
+        // so the field grows straight out from the cylinder instead
+        let r = (p[0]*p[0] + p[2]*p[2]).sqrt();
+        let (q, out) = if r > self.radius {
+            let s = self.radius/r;
+            (Vec3f::new(p[0]*s, p[1], p[2]*s), r - self.radius)
+        } else {
+            (p, 0.0)
+        };
+        let (sin, cos) = (-self.rate*q[1]).sin_cos();
+        self.inner.dist(Vec3f::new(cos*q[0] + sin*q[2], q[1], cos*q[2] - sin*q[0])) + out
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.inner.lipschitz()*(1.0 + (self.rate*self.radius).powi(2)).sqrt()
+    }
+}
+
+/// Like `Twist`, the result is not an exact distance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Displace<S> {
+    pub inner: S,
+    pub noise: Noise,
+    pub amplitude: f32
+}
+
+impl<S: Sdf> Sdf for Displace<S> {
+    fn dist(&self, p: Vec3f) -> f32 {
+        self.inner.dist(p) - self.amplitude*self.noise.at(p)
+    }
+
+    fn lipschitz(&self) -> f32 {
+        self.inner.lipschitz() + self.amplitude.abs()*self.noise.lipschitz()
+    }
+
+    // Far from the surface the undisplaced shape gives longer steps
+    fn bound(&self, p: Vec3f) -> f32 {
+        (self.inner.bound(p) - self.amplitude.abs()).max(self.dist(p)/self.lipschitz())
+    }
+}
+
+/// Distance field placed in a `Scene`, found by sphere tracing inside its bounds.
+/// Rays starting inside, like refracted ones, are traced to where they leave.
+pub struct SdfObject<'a> {
+    pub sdf: Box<dyn Sdf + 'a>,
+    /// Box the whole surface lies in.
+    pub bounds: Aabb,
+    pub material: &'a Material,
+    pub tracer: Tracer
+}
+
+impl<'a> SdfObject<'a> {
+    pub fn new<S: Sdf + 'a>(sdf: S, bounds: Aabb, material: &'a Material) -> Self {
+        Self { sdf: Box::new(sdf), bounds, material, tracer: Tracer::default() }
+    }
+
+    pub fn with_tracer(self, tracer: Tracer) -> Self {
+        Self { tracer, ..self }
+    }
+}
+
+impl<'a> Hittable for SdfObject<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let inv_dir = Vec3f::new(1.0/dir[0], 1.0/dir[1], 1.0/dir[2]);
+        let (t0, t1) = self.bounds.ray_span(orig, inv_dir, t_max)?;
+        let t = self.tracer.trace(&self.sdf, orig, dir, t0, t1).hit?;
+        let point = orig + dir*t;
+        let normal = self.sdf.normal(point);
+        let uv = Vec2f::new(
+            0.5 + f32::atan2(normal[2], normal[0])/(2.0*PI),
+            0.5 - normal[1].asin()/PI
+        );
+        Some(Hit { dist: t, point, normal, material: self.material, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn primitives() {
+        let x = Vec3f::new(3.0, 0.0, 0.0);
+        let y = Vec3f::new(0.0, 3.0, 0.0);
+        assert!(close(Sphere::new(1.0).dist(x), 2.0));
+        assert!(close(Cuboid::new(Vec3f::one()).dist(x), 2.0));
+        assert!(close(Cuboid::new(Vec3f::one()).dist(Vec3f::new(2.0, 2.0, 1.0)), 2f32.sqrt()));
+        assert!(close(RoundCuboid::new(Vec3f::one(), 0.5).dist(Vec3f::new(2.0, 2.0, 2.0)), 3f32.sqrt()*1.5 - 0.5));
+        assert!(close(Torus::new(2.0, 0.5).dist(x), 0.5));
+        assert!(close(Torus::new(2.0, 0.5).dist(Vec3f::zero()), 1.5));
+        assert!(close(Capsule::new(-y, y, 1.0).dist(x), 2.0));
+        assert!(close(Capsule::new(-y, y, 1.0).dist(y*2.0), 2.0));
+        assert!(close(Cylinder::new(1.0, 1.0).dist(x), 2.0));
+        assert!(close(Cylinder::new(1.0, 1.0).dist(y), 2.0));
+        assert!(close(Cylinder::new(1.0, 1.0).dist(Vec3f::zero()), -1.0));
+        assert!(close(Cone::new(1.0, 2.0).dist(y), 1.0));
+        assert!(close(Cone::new(1.0, 2.0).dist(-y), 3.0));
+        assert!(close(Cone::new(1.0, 2.0).dist(x), 2.0));
+        assert!(Cone::new(1.0, 2.0).dist(Vec3f::new(0.0, 0.5, 0.0)) < 0.0);
+        assert!(close(Plane::new(Vec3f::new(0.0, 2.0, 0.0), 1.0).dist(y), 2.0));
+    }
+
+    #[test]
+    fn combinators_and_domain() {
+        let a = Sphere::new(1.0);
+        let b = Sphere::new(1.0).translate(Vec3f::new(1.5, 0.0, 0.0));
+        let p = Vec3f::new(0.75, 0.0, 0.0);
+        assert!(close(a.union(b).dist(Vec3f::new(-2.0, 0.0, 0.0)), 1.0));
+        assert!(close(a.intersection(b).dist(p), -0.25));
+        assert!(close(a.subtraction(b).dist(p), 0.25));
+        assert!(a.smooth_union(b, 0.5).dist(p) < a.union(b).dist(p));
+        assert_eq!(a.smooth_union(b, 0.5).dist(Vec3f::new(-2.0, 0.0, 0.0)), 1.0);
+
+        let bar = Cuboid::new(Vec3f::new(2.0, 0.5, 0.5));
+        assert!(close(bar.rotate(Vec3f::new(0.0, 0.0, 1.0), PI/2.0).dist(Vec3f::new(0.0, 3.0, 0.0)), 1.0));
+        assert!(close(a.scale(2.0).dist(Vec3f::new(3.0, 0.0, 0.0)), 1.0));
+        let row = a.repeat(Vec3f::new(4.0, 0.0, 0.0));
+        assert!(close(row.dist(Vec3f::new(40.0, 0.0, 0.0)), -1.0));
+        assert!(close(row.dist(Vec3f::new(40.0, 3.0, 0.0)), 2.0));
+        // A quarter turn at y = 1 brings the long side of the bar round to Z
+        let twisted = Cuboid::new(Vec3f::new(2.0, 2.0, 0.5)).twist(PI/2.0, 2.1);
+        assert!(twisted.dist(Vec3f::new(0.0, 1.0, 1.5)) < 0.0);
+        assert!(twisted.dist(Vec3f::new(1.5, 1.0, 0.0)) > 0.0);
+
+        let noise = Noise::new(7);
+        let bumpy = a.displace(noise, 0.2);
+        let q = Vec3f::new(0.3, 0.8, 0.5);
+        assert!(close(bumpy.dist(q), a.dist(q) - 0.2*noise.at(q)));
+
+        let boxed: Box<dyn Sdf> = Box::new(a.translate(Vec3f::new(0.0, 2.0, 0.0)));
+        let n = boxed.normal(Vec3f::new(0.0, 4.0, 0.0));
+        assert!((n - Vec3f::new(0.0, 1.0, 0.0)).norm() < 1e-3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn mirrored_scale() {
+        let _ = Sphere::new(1.0).scale(-2.0);
+    }
+
+    #[test]
+    fn scene_object_matches_analytic_sphere() {
+        let material = Material::default();
+        let center = Vec3f::new(0.5, -0.5, -6.0);
+        let analytic = crate::sphere::Sphere::new(center, 1.5, &material);
+        let r = Vec3f::one()*1.5;
+        let marched = SdfObject::new(Sphere::new(1.5).translate(center), Aabb::new(center - r, center + r), &material);
+        for (orig, dir) in [
+            (Vec3f::zero(), Vec3f::new(0.0, 0.0, -1.0)),
+            (Vec3f::zero(), Vec3f::new(0.1, -0.2, -1.0).normalize()),
+            // From inside, as refracted rays are
+            (center, Vec3f::new(0.3, 1.0, 0.2).normalize())
+        ] {
+            let (a, b) = (analytic.hit(orig, dir, f32::MAX).unwrap(), marched.hit(orig, dir, f32::MAX).unwrap());
+            assert!((a.dist - b.dist).abs() < 1e-3, "{} {}", a.dist, b.dist);
+            assert!((a.normal - b.normal).norm() < 1e-2);
+        }
+        assert!(marched.hit(Vec3f::zero(), Vec3f::new(0.0, 0.0, -1.0), 4.0).is_none());
+        assert!(marched.hit(Vec3f::zero(), Vec3f::new(0.0, 1.0, 0.0), f32::MAX).is_none());
+    }
+
+    #[test]
+    fn lipschitz_bounds() {
+        // Neither the twisted bar nor the noise displaced ball change faster than they claim
+        let bar = Cuboid::new(Vec3f::new(2.0, 2.0, 0.5)).twist(PI/2.0, 2.1);
+        let bumpy = Sphere::new(1.0).displace(Noise::new(3).with_frequency(4.0), 0.3).twist(0.5, 1.3);
+        let step = Vec3f::new(1e-3, -2e-3, 1e-3);
+        for i in 0..500 {
+            let p = Vec3f::new((i as f32*0.37).sin(), (i as f32*0.11).cos(), (i as f32*0.23).sin())*2.5;
+            let q = Vec3f::new((i as f32*0.71).cos(), (i as f32*0.53).sin(), (i as f32*0.19).cos())*2.5;
+            for (name, sdf) in [("bar", &bar as &dyn Sdf), ("bumpy", &bumpy)] {
+                let bound = sdf.lipschitz();
+                assert!((sdf.dist(p) - sdf.dist(q)).abs() <= bound*(p - q).norm() + 1e-4, "{}", name);
+                assert!((sdf.dist(p + step) - sdf.dist(p)).abs() <= bound*step.norm() + 1e-5, "{}", name);
+            }
+            assert!(bumpy.bound(p) <= bumpy.dist(p).max(0.0) + 1e-4);
+        }
+    }
+}
@@ -1,4 +1,9 @@
-use crate::geometry::{Vec3f, Vec4f};
+use std::f32::consts::PI;
+
+use crate::bvh::Aabb;
+use crate::geometry::{Vec2f, Vec3f};
+use crate::hittable::{Hit, Hittable};
+pub use crate::material::Material;
 
 pub struct Sphere<'a> {
     pub center: Vec3f,
@@ -33,23 +38,46 @@ impl<'a> Sphere<'a> {
             Some(t0)
         }
     }
+
 }
 
-#[derive(Clone,Copy)]
-pub struct Material {
-    pub diffuse_color: Vec3f,
-    pub albedo: Vec4f,
-    pub refractive_index: f32,
-    pub specular_exp: f32
+impl<'a> Hittable for Sphere<'a> {
+    fn hit(&self, orig: Vec3f, dir: Vec3f, t_max: f32) -> Option<Hit<'_>> {
+        let dist = self.ray_intersect(orig, dir).filter(|&d| d < t_max)?;
+        let point = orig + dir*dist;
+        let normal = (point - self.center).normalize();
+        let uv = Vec2f::new(
+            0.5 + f32::atan2(normal[2], normal[0])/(2.0*PI),
+            0.5 - normal[1].asin()/PI
+        );
+        Some(Hit { dist, point, normal, material: self.material, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = Vec3f::one()*self.radius;
+        Aabb::new(self.center - r, self.center + r)
+    }
 }
 
-impl Default for Material {
-    fn default() -> Self {
-        Self { 
-            diffuse_color: Vec3f::zero(),
-            albedo: Vec4f::from([1.0, 0.0, 0.0, 0.0]),
-            refractive_index: 1.0,
-            specular_exp: 0.0
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hit_normal_and_uv() {
+        let material = Material::default();
+        let sphere = Sphere::new(Vec3f::new(0.0, 0.0, -5.0), 1.0, &material);
+        let h = sphere.hit(Vec3f::zero(), Vec3f::new(0.0, 0.0, -1.0), f32::MAX).unwrap();
+        assert_eq!(h.dist, 4.0);
+        assert_eq!(h.point, Vec3f::new(0.0, 0.0, -4.0));
+        assert_eq!(h.normal, Vec3f::new(0.0, 0.0, 1.0));
+        assert!((h.uv - Vec2f::new(0.75, 0.5)).norm() < 1e-6);
+        // From the center the ray leaves through the top, which maps to v = 0
+        let h = sphere.hit(sphere.center, Vec3f::new(0.0, 1.0, 0.0), f32::MAX).unwrap();
+        assert_eq!(h.dist, 1.0);
+        assert_eq!(h.normal, Vec3f::new(0.0, 1.0, 0.0));
+        assert!(h.uv[1].abs() < 1e-6);
+        assert!(sphere.hit(Vec3f::zero(), Vec3f::new(0.0, 0.0, -1.0), 3.0).is_none());
+        assert!(sphere.hit(Vec3f::zero(), Vec3f::new(0.0, 1.0, 0.0), f32::MAX).is_none());
     }
-}
\ No newline at end of file
+}
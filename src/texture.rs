@@ -0,0 +1,266 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geometry::{Vec2f, Vec3f};
+use crate::noise::{lerp, Fractal, Noise};
+use crate::scenefile::Quoted;
+
+/// RGB image with channels in 0..1, as used by image textures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3f>
+}
+
+impl Image {
+    fn pixel(&self, x: isize, y: isize) -> Vec3f {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        self.pixels[y*self.width + x]
+    }
+
+    /// Bilinearly filtered color at `uv`, repeating outside of 0..1.
+    /// `v` runs upwards, so the first row of the file is at the top.
+    pub fn sample(&self, uv: Vec2f) -> Vec3f {
+        let x = uv[0]*self.width as f32 - 0.5;
+        let y = (1.0 - uv[1])*self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = self.pixel(x0, y0)*(1.0 - fx) + self.pixel(x0 + 1, y0)*fx;
+        let bottom = self.pixel(x0, y0 + 1)*(1.0 - fx) + self.pixel(x0 + 1, y0 + 1)*fx;
+        top*(1.0 - fy) + bottom*fy
+    }
+}
+
+/// Piecewise linear map from 0..1 to colors, through stops sorted by position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient(pub Vec<(f32, Vec3f)>);
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f32, Vec3f)>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self(stops)
+    }
+
+    pub fn at(&self, t: f32) -> Vec3f {
+        let stops = &self.0;
+        match stops.iter().position(|s| s.0 > t) {
+            None => stops.last().map_or(Vec3f::zero(), |s| s.1),
+            Some(0) => stops[0].1,
+            Some(i) => {
+                let (a, b) = (stops[i - 1], stops[i]);
+                lerp(a.1, b.1, (t - a.0)/(b.0 - a.0))
+            }
+        }
+    }
+}
+
+/// Solid patterns built from layered noise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Bands along X bent by turbulence.
+    Marble,
+    /// Rings around the Y axis with a slight wobble.
+    Wood,
+    Turbulence,
+    /// Soft fractal noise.
+    Clouds
+}
+
+impl NoisePattern {
+    pub const ALL: [NoisePattern; 4] = [NoisePattern::Marble, NoisePattern::Wood, NoisePattern::Turbulence, NoisePattern::Clouds];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoisePattern::Marble => "marble",
+            NoisePattern::Wood => "wood",
+            NoisePattern::Turbulence => "turbulence",
+            NoisePattern::Clouds => "clouds"
+        }
+    }
+
+    /// Colors used when a texture gives no gradient of its own.
+    pub fn default_gradient(&self) -> Gradient {
+        let c = Vec3f::new;
+        Gradient::new(match self {
+            NoisePattern::Marble => vec![(0.0, c(0.95, 0.95, 0.92)), (0.7, c(0.75, 0.75, 0.78)), (1.0, c(0.2, 0.2, 0.25))],
+            NoisePattern::Wood => vec![(0.0, c(0.55, 0.35, 0.17)), (1.0, c(0.3, 0.16, 0.07))],
+            NoisePattern::Turbulence => vec![(0.0, c(0.0, 0.0, 0.0)), (1.0, c(1.0, 1.0, 1.0))],
+            NoisePattern::Clouds => vec![(0.3, c(0.25, 0.45, 0.85)), (0.7, c(1.0, 1.0, 1.0))]
+        })
+    }
+
+    /// Pattern value in 0..1 at `p`, already scaled to noise space. The
+    /// pattern picks how `noise` folds its octaves, everything else is kept.
+    pub fn value(&self, p: Vec3f, noise: &Noise) -> f32 {
+        let fbm = noise.with_fractal(Fractal::Fbm);
+        let turbulence = noise.with_fractal(Fractal::Billow);
+        match self {
+            NoisePattern::Marble => 0.5 + 0.5*(p[0]*2.0 + 8.0*turbulence.unit(p)).sin(),
+            NoisePattern::Wood => {
+                let r = (p[0]*p[0] + p[2]*p[2]).sqrt() + 0.4*fbm.unit(p*0.5);
+                (r*4.0).fract()
+            },
+            NoisePattern::Turbulence => turbulence.unit(p),
+            NoisePattern::Clouds => fbm.unit(p)
+        }
+    }
+}
+
+/// Noise pattern evaluated at the world position, colored through a gradient.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseTexture {
+    pub pattern: NoisePattern,
+    /// Pattern features per world unit.
+    pub scale: f32,
+    /// Seed, basis and octaves of the underlying noise, its fractal is set by the pattern.
+    pub noise: Noise,
+    pub gradient: Gradient
+}
+
+impl NoiseTexture {
+    pub fn new(pattern: NoisePattern) -> Self {
+        Self { pattern, scale: 1.0, noise: Noise::default(), gradient: pattern.default_gradient() }
+    }
+
+    pub fn eval(&self, point: Vec3f) -> Vec3f {
+        self.gradient.at(self.pattern.value(point*self.scale, &self.noise))
+    }
+}
+
+/// Function from surface UV and world position to a color.
+pub type ProceduralFn = dyn Fn(Vec2f, Vec3f) -> Vec3f + Send + Sync;
+
+/// Color that varies over a surface, evaluated at every hit.
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Vec3f),
+    /// Alternates two textures over the UV grid, `scale` cells per UV unit.
+    Checker { even: Box<Texture>, odd: Box<Texture>, scale: f32 },
+    /// Image stretched once over the UV square. `path` is where it was loaded from.
+    Image { path: String, image: Arc<Image> },
+    Noise(NoiseTexture),
+    Procedural(Arc<ProceduralFn>)
+}
+
+impl Default for Texture {
+    fn default() -> Self {
+        Texture::Constant(Vec3f::zero())
+    }
+}
+
+impl From<Vec3f> for Texture {
+    fn from(c: Vec3f) -> Self {
+        Texture::Constant(c)
+    }
+}
+
+impl Texture {
+    pub fn checker(even: Texture, odd: Texture, scale: f32) -> Self {
+        Texture::Checker { even: Box::new(even), odd: Box::new(odd), scale }
+    }
+
+    pub fn procedural<F: Fn(Vec2f, Vec3f) -> Vec3f + Send + Sync + 'static>(f: F) -> Self {
+        Texture::Procedural(Arc::new(f))
+    }
+
+    pub fn eval(&self, uv: Vec2f, point: Vec3f) -> Vec3f {
+        match self {
+            Texture::Constant(c) => *c,
+            Texture::Checker { even, odd, scale } => {
+                let cell = (uv[0]*scale).floor() as i32 + (uv[1]*scale).floor() as i32;
+                if cell & 1 == 0 { even.eval(uv, point) } else { odd.eval(uv, point) }
+            },
+            Texture::Image { image, .. } => image.sample(uv),
+            Texture::Noise(noise) => noise.eval(point),
+            Texture::Procedural(f) => f(uv, point)
+        }
+    }
+
+    /// Whether any part of the texture is code, which cannot be written to a scene file.
+    pub fn is_procedural(&self) -> bool {
+        match self {
+            Texture::Checker { even, odd, .. } => even.is_procedural() || odd.is_procedural(),
+            Texture::Procedural(_) => true,
+            _ => false
+        }
+    }
+
+    /// The color if it is the same everywhere.
+    pub fn constant(&self) -> Option<Vec3f> {
+        match self {
+            Texture::Constant(c) => Some(*c),
+            _ => None
+        }
+    }
+}
+
+/// Scene file syntax. Procedural textures are code, formatting one fails.
+impl fmt::Display for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Texture::Constant(c) => write!(f, "{} {} {}", c[0], c[1], c[2]),
+            Texture::Checker { even, odd, scale } => write!(f, "checker {{ even {} odd {} scale {} }}", even, odd, scale),
+            Texture::Image { path, .. } => write!(f, "image {}", Quoted(path)),
+            Texture::Noise(noise) => {
+                let n = &noise.noise;
+                write!(f, "{} {{ scale {} seed {} basis {} octaves {} lacunarity {} gain {}",
+                    noise.pattern.name(), noise.scale, n.seed, n.basis, n.octaves, n.lacunarity, n.gain)?;
+                if noise.gradient != noise.pattern.default_gradient() {
+                    for (t, c) in &noise.gradient.0 {
+                        write!(f, " stop {} {} {} {}", t, c[0], c[1], c[2])?;
+                    }
+                }
+                f.write_str(" }")
+            },
+            Texture::Procedural(_) => Err(fmt::Error)
+        }
+    }
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_procedural() {
+            f.write_str("Texture(procedural)")
+        } else {
+            write!(f, "Texture({})", self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checker_and_image() {
+        let red = Vec3f::new(1.0, 0.0, 0.0);
+        let checker = Texture::checker(red.into(), Vec3f::zero().into(), 2.0);
+        assert_eq!(checker.eval(Vec2f::new(0.1, 0.1), Vec3f::zero()), red);
+        assert_eq!(checker.eval(Vec2f::new(0.6, 0.1), Vec3f::zero()), Vec3f::zero());
+
+        let image = Image { width: 2, height: 1, pixels: vec![red, Vec3f::one()] };
+        assert_eq!(image.sample(Vec2f::new(0.25, 0.5)), red);
+        // Halfway between the pixel centers the colors blend
+        assert_eq!(image.sample(Vec2f::new(0.5, 0.5)), Vec3f::new(1.0, 0.5, 0.5));
+        // and wrap around at the edges
+        assert_eq!(image.sample(Vec2f::new(0.0, 0.5)), Vec3f::new(1.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn noise_patterns() {
+        let gradient = Gradient::new(vec![(1.0, Vec3f::one()), (0.0, Vec3f::zero())]);
+        assert_eq!(gradient.at(0.25), Vec3f::one()*0.25);
+        assert_eq!(gradient.at(-1.0), Vec3f::zero());
+        assert_eq!(gradient.at(2.0), Vec3f::one());
+        for pattern in NoisePattern::ALL {
+            for i in 0..200 {
+                let p = Vec3f::new(i as f32*0.37, i as f32*0.11 - 5.0, i as f32*0.23);
+                let v = pattern.value(p, &Noise::new(3).with_octaves(5));
+                assert!((0.0..=1.0).contains(&v), "{} {}", pattern.name(), v);
+            }
+        }
+    }
+}